@@ -0,0 +1,269 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, IntoInnerError, Read, Write};
+use std::ops::RangeInclusive;
+
+use flate2::bufread::GzDecoder;
+use flate2::write::GzEncoder;
+
+/// A writer that must be explicitly finished to flush everything to disk, e.g.
+/// to write a compression trailer.
+pub trait Encoder: Write + Send {
+    /// Finishes the encoded stream and the writer underneath it.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl Encoder for BufWriter<File> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.into_inner().map_err(IntoInnerError::into_error)?;
+        Ok(())
+    }
+}
+
+/// Encodes and decodes the on-disk format of golden files, e.g. compression.
+/// Diffs are computed on the decoded content.
+pub trait Codec: fmt::Debug + Send + Sync {
+    fn encoder(&self, w: Box<dyn Encoder>) -> io::Result<Box<dyn Encoder>>;
+    fn decoder(&self, r: Box<dyn BufRead>) -> io::Result<Box<dyn Read>>;
+}
+
+// Returns an error if |level| is outside of |range|.
+fn check_level(name: &str, level: i64, range: RangeInclusive<i64>) -> io::Result<()> {
+    if !range.contains(&level) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} compression level {level} not in {range:?}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Gzip compression, registered for "gz" by default.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Gzip {
+    level: u32,
+}
+
+impl Gzip {
+    /// Compression level from 0 (none) to 9 (best). Defaults to 6.
+    pub fn new(level: u32) -> Self {
+        Self { level }
+    }
+}
+
+impl Default for Gzip {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+impl Encoder for GzEncoder<Box<dyn Encoder>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+impl Codec for Gzip {
+    fn encoder(&self, w: Box<dyn Encoder>) -> io::Result<Box<dyn Encoder>> {
+        check_level("gzip", self.level.into(), 0..=9)?;
+        Ok(Box::new(GzEncoder::new(w, flate2::Compression::new(self.level))))
+    }
+
+    fn decoder(&self, r: Box<dyn BufRead>) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(GzDecoder::new(r)))
+    }
+}
+
+/// Zstandard compression, registered for "zst" with the `zstd` feature.
+#[cfg(feature = "zstd")]
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Zstd {
+    level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl Zstd {
+    /// Compression level from 1 to 22, or negative for faster compression.
+    /// Defaults to 3.
+    pub fn new(level: i32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Default for Zstd {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Encoder for zstd::Encoder<'static, Box<dyn Encoder>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for Zstd {
+    fn encoder(&self, w: Box<dyn Encoder>) -> io::Result<Box<dyn Encoder>> {
+        let range = zstd::compression_level_range();
+        check_level("zstd", self.level.into(), (*range.start()).into()..=(*range.end()).into())?;
+        Ok(Box::new(zstd::Encoder::new(w, self.level)?))
+    }
+
+    fn decoder(&self, r: Box<dyn BufRead>) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(zstd::Decoder::with_buffer(r)?))
+    }
+}
+
+/// Xz compression, registered for "xz" with the `xz` feature.
+#[cfg(feature = "xz")]
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Xz {
+    level: u32,
+}
+
+#[cfg(feature = "xz")]
+impl Xz {
+    /// Compression level from 0 (fastest) to 9 (best). Defaults to 6.
+    pub fn new(level: u32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "xz")]
+impl Default for Xz {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+#[cfg(feature = "xz")]
+impl Encoder for xz2::write::XzEncoder<Box<dyn Encoder>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+#[cfg(feature = "xz")]
+impl Codec for Xz {
+    fn encoder(&self, w: Box<dyn Encoder>) -> io::Result<Box<dyn Encoder>> {
+        check_level("xz", self.level.into(), 0..=9)?;
+        Ok(Box::new(xz2::write::XzEncoder::new(w, self.level)))
+    }
+
+    fn decoder(&self, r: Box<dyn BufRead>) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(xz2::bufread::XzDecoder::new_multi_decoder(r)))
+    }
+}
+
+/// Bzip2 compression, registered for "bz2" with the `bzip2` feature.
+#[cfg(feature = "bzip2")]
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Bzip2 {
+    level: u32,
+}
+
+#[cfg(feature = "bzip2")]
+impl Bzip2 {
+    /// Compression level from 1 (fastest) to 9 (best). Defaults to 6.
+    pub fn new(level: u32) -> Self {
+        Self { level }
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl Default for Bzip2 {
+    fn default() -> Self {
+        Self::new(6)
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl Encoder for bzip2::write::BzEncoder<Box<dyn Encoder>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.finish()
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl Codec for Bzip2 {
+    fn encoder(&self, w: Box<dyn Encoder>) -> io::Result<Box<dyn Encoder>> {
+        check_level("bzip2", self.level.into(), 1..=9)?;
+        Ok(Box::new(bzip2::write::BzEncoder::new(w, bzip2::Compression::new(self.level))))
+    }
+
+    fn decoder(&self, r: Box<dyn BufRead>) -> io::Result<Box<dyn Read>> {
+        Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(r)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use super::*;
+
+    fn encode(codec: &dyn Codec, data: &[u8]) -> io::Result<Vec<u8>> {
+        let f = tempfile::NamedTempFile::new()?;
+        let mut w = codec.encoder(Box::new(BufWriter::new(f.reopen()?)))?;
+        w.write_all(data)?;
+        w.finish()?;
+        std::fs::read(f.path())
+    }
+
+    fn round_trip(codec: &dyn Codec) {
+        let data = "golden line\n".repeat(1000);
+        let encoded = encode(codec, data.as_bytes()).unwrap();
+        assert_ne!(encoded, data.as_bytes(), "{codec:?}");
+        let mut decoded = Vec::new();
+        let r = Box::new(BufReader::new(io::Cursor::new(encoded)));
+        codec.decoder(r).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data.as_bytes(), "{codec:?}");
+    }
+
+    fn check_levels(codecs: &[&dyn Codec], bad: &[&dyn Codec]) {
+        for &codec in codecs {
+            round_trip(codec);
+        }
+        for &codec in bad {
+            let err = encode(codec, b"x").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn gzip() {
+        check_levels(&[&Gzip::default(), &Gzip::new(0), &Gzip::new(9)], &[&Gzip::new(10)]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let range = zstd::compression_level_range();
+        check_levels(
+            &[&Zstd::default(), &Zstd::new(1), &Zstd::new(*range.end())],
+            &[&Zstd::new(*range.end() + 1), &Zstd::new(*range.start() - 1)],
+        );
+    }
+
+    #[cfg(feature = "xz")]
+    #[test]
+    fn xz() {
+        check_levels(&[&Xz::default(), &Xz::new(0), &Xz::new(9)], &[&Xz::new(10)]);
+    }
+
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2() {
+        check_levels(
+            &[&Bzip2::default(), &Bzip2::new(1), &Bzip2::new(9)],
+            &[&Bzip2::new(0), &Bzip2::new(10)],
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+
+// Maximum number of lines buffered from each side while looking for a
+// resynchronization point. Bounds memory use on large golden files.
+const WINDOW: usize = 1024;
+// Maximum number of lines buffered from each side when the windows have no
+// line in common and a closer resynchronization point is searched for.
+const LOOKAHEAD: usize = 16 * WINDOW;
+// Lines longer than this are split into several chunks, at a character boundary.
+const LINE_LIMIT: usize = 1 << 16;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Size of the difference between a golden file and the actual output. For
+/// binary files, lines are rows of the hex dump.
+#[must_use]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DiffStats {
+    pub hunks: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl DiffStats {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hunks == 0
+    }
+}
+
+// A line, or a piece of one if it is longer than |LINE_LIMIT|. |more| is set
+// on all but the last piece of a line.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+struct Chunk {
+    text: String,
+    more: bool,
+}
+
+struct Lines {
+    r: BufReader<Box<dyn Read>>,
+    eof: bool,
+    line: usize,
+    carry: Vec<u8>,
+}
+
+impl Lines {
+    fn new(r: Box<dyn Read>) -> Self {
+        Self { r: BufReader::new(r), eof: false, line: 1, carry: Vec::new() }
+    }
+
+    fn next(&mut self) -> io::Result<Option<Chunk>> {
+        let mut buf = mem::take(&mut self.carry);
+        let lim = (LINE_LIMIT - buf.len()) as u64;
+        let n = (&mut self.r).take(lim).read_until(b'\n', &mut buf)?;
+        if buf.is_empty() {
+            self.eof = true;
+            return Ok(None);
+        }
+        let line = self.line;
+        let mut more = false;
+        if buf.ends_with(b"\n") {
+            self.line += 1;
+        } else if n != 0 && buf.len() == LINE_LIMIT {
+            // Hit the limit in the middle of a line. Don't split a multibyte
+            // character across chunks.
+            let en = utf8_boundary(&buf);
+            self.carry = buf.split_off(en);
+            more = !self.carry.is_empty() || !self.r.fill_buf()?.is_empty();
+        }
+        String::from_utf8(buf).map(|text| Some(Chunk { text, more })).map_err(|e| {
+            let msg = format!("invalid UTF-8 on line {}: {}", line, e.utf8_error());
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })
+    }
+
+    fn fill(&mut self, q: &mut VecDeque<Chunk>, n: usize) -> io::Result<()> {
+        while !self.eof && q.len() < n {
+            if let Some(l) = self.next()? {
+                q.push_back(l);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads all lines of |r|, the same lines as passed on by |LineDiff::run|.
+pub(crate) fn read_lines(r: Box<dyn Read>) -> io::Result<Vec<String>> {
+    let mut lines = Lines::new(r);
+    let mut chunks = Vec::new();
+    while let Some(c) = lines.next()? {
+        chunks.push(c);
+    }
+    let mut v = Vec::new();
+    join(chunks.iter(), |l| {
+        v.push(l.to_owned());
+        Ok(())
+    })?;
+    Ok(v)
+}
+
+// Joins |chunks| back into lines and passes each line to |f|.
+fn join<'a>(
+    chunks: impl Iterator<Item = &'a Chunk>,
+    mut f: impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut line = String::new();
+    for c in chunks {
+        if line.is_empty() && !c.more {
+            f(&c.text)?;
+            continue;
+        }
+        line += &c.text;
+        if !c.more {
+            f(&line)?;
+            line.clear();
+        }
+    }
+    if !line.is_empty() {
+        f(&line)?;
+    }
+    Ok(())
+}
+
+// Passes the chunks in |group|, which start and end on line boundaries on
+// both sides, to |f| as whole lines. Lines that are only partly equal are
+// reported as removed and added in full.
+fn flush(
+    group: &mut Vec<(Op, Chunk)>,
+    f: &mut impl FnMut(Op, &str) -> io::Result<()>,
+) -> io::Result<()> {
+    if group.iter().all(|(op, _)| *op == Op::Equal) {
+        join(group.iter().map(|(_, c)| c), |l| f(Op::Equal, l))?;
+    } else {
+        let old = group.iter().filter(|(op, _)| *op != Op::Insert);
+        join(old.map(|(_, c)| c), |l| f(Op::Delete, l))?;
+        let new = group.iter().filter(|(op, _)| *op != Op::Delete);
+        join(new.map(|(_, c)| c), |l| f(Op::Insert, l))?;
+    }
+    group.clear();
+    Ok(())
+}
+
+// Returns the index at which a trailing incomplete UTF-8 sequence starts, or
+// the length of |b| if it ends on a character boundary.
+fn utf8_boundary(b: &[u8]) -> usize {
+    for i in (b.len().saturating_sub(4)..b.len()).rev() {
+        let c = b[i];
+        if c & 0xC0 != 0x80 {
+            let w = match c {
+                0x00..=0x7F => 1,
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                _ => 4,
+            };
+            return if i + w > b.len() { i } else { b.len() };
+        }
+    }
+    b.len()
+}
+
+// Streaming line diff. Runs Myers' algorithm over a sliding window of lines
+// from each side, only committing edits up to the last matching line so that
+// insertions and deletions resynchronize in the next window.
+#[must_use]
+pub(crate) struct LineDiff {
+    old: Lines,
+    new: Lines,
+    a: VecDeque<Chunk>,
+    b: VecDeque<Chunk>,
+}
+
+impl LineDiff {
+    pub(crate) fn new(old: Box<dyn Read>, new: Box<dyn Read>) -> Self {
+        Self { old: Lines::new(old), new: Lines::new(new), a: VecDeque::new(), b: VecDeque::new() }
+    }
+
+    pub(crate) fn run(
+        mut self,
+        mut f: impl FnMut(Op, &str) -> io::Result<()>,
+    ) -> io::Result<DiffStats> {
+        let mut st = DiffStats::default();
+        let mut in_hunk = false;
+        let mut emit = |op: Op, l: &str| {
+            match op {
+                Op::Equal => in_hunk = false,
+                Op::Delete | Op::Insert => {
+                    if !in_hunk {
+                        st.hunks += 1;
+                        in_hunk = true;
+                    }
+                    if op == Op::Delete {
+                        st.removed += 1;
+                    } else {
+                        st.added += 1;
+                    }
+                }
+            }
+            f(op, l)
+        };
+        // Chunks of long lines are collected until both sides are back at a
+        // line boundary, so that callers only ever see whole lines.
+        let mut group = Vec::new();
+        let (mut old_open, mut new_open) = (false, false);
+        loop {
+            self.old.fill(&mut self.a, WINDOW)?;
+            self.new.fill(&mut self.b, WINDOW)?;
+            if self.a.is_empty() && self.b.is_empty() {
+                break;
+            }
+
+            // The queues may hold more than a window after a lookahead.
+            let last = self.old.eof && self.new.eof && self.a.len().max(self.b.len()) <= WINDOW;
+            let a = self.a.make_contiguous();
+            let b = self.b.make_contiguous();
+            let mut ops = myers(&a[..a.len().min(WINDOW)], &b[..b.len().min(WINDOW)]);
+            // Edits after the last equal line may only exist because the window
+            // cut off the matching lines, so leave them for the next round.
+            let take = if last {
+                ops.len()
+            } else if let Some(i) = ops.iter().rposition(|&op| op == Op::Equal) {
+                i + 1
+            } else {
+                let (i, j) = self.resync()?;
+                ops = [Op::Delete].repeat(i);
+                ops.extend([Op::Insert].repeat(j));
+                ops.len()
+            };
+
+            for &op in &ops[..take] {
+                let l = match op {
+                    Op::Equal => {
+                        self.b.pop_front();
+                        self.a.pop_front()
+                    }
+                    Op::Delete => self.a.pop_front(),
+                    Op::Insert => self.b.pop_front(),
+                };
+                let c = l.unwrap_or_default();
+                if op != Op::Insert {
+                    old_open = c.more;
+                }
+                if op != Op::Delete {
+                    new_open = c.more;
+                }
+                group.push((op, c));
+                if !old_open && !new_open {
+                    flush(&mut group, &mut emit)?;
+                }
+            }
+        }
+        flush(&mut group, &mut emit)?;
+        Ok(st)
+    }
+
+    // Called when the windows have no line in common. Reads up to |LOOKAHEAD|
+    // lines from each side and returns the number of lines to delete and
+    // insert before the closest common line, or all buffered lines if there
+    // is none, so that insertions and deletions larger than a window don't
+    // turn into replaced blocks.
+    fn resync(&mut self) -> io::Result<(usize, usize)> {
+        self.old.fill(&mut self.a, LOOKAHEAD)?;
+        self.new.fill(&mut self.b, LOOKAHEAD)?;
+        let mut first = HashMap::new();
+        for (j, l) in self.b.iter().enumerate() {
+            first.entry(l).or_insert(j);
+        }
+        let closest = self
+            .a
+            .iter()
+            .enumerate()
+            .filter_map(|(i, l)| first.get(l).map(|&j| (i, j)))
+            .min_by_key(|&(i, j)| i + j);
+        Ok(closest.unwrap_or((self.a.len(), self.b.len())))
+    }
+}
+
+// Myers' O(ND) diff. Only the active diagonals of each round are kept for
+// backtracking, so memory is O(D^2) rather than O(D(N+M)).
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let off = n + m + 1;
+    let idx = |k: isize| (k + off) as usize;
+    let mut v = vec![0isize; 2 * off as usize + 1];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=n + m {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                trace.push(v[idx(-d)..=idx(d)].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[idx(-d)..=idx(d)].to_vec());
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let pv = &trace[(d - 1) as usize];
+        let get = |k: isize| pv[(k + d - 1) as usize];
+        let k = x - y;
+        let pk = if k == -d || (k != d && get(k - 1) < get(k + 1)) { k + 1 } else { k - 1 };
+        let px = get(pk);
+        let py = px - pk;
+        while x > px && y > py {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if pk == k + 1 { Op::Insert } else { Op::Delete });
+        x = px;
+        y = py;
+    }
+    ops.extend((0..x).map(|_| Op::Equal));
+    ops.reverse();
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+
+    use super::*;
+
+    fn reader(s: &str) -> Box<dyn Read> {
+        Box::new(io::Cursor::new(s.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn utf8_boundary_cut_sequences() {
+        for c in ['é', '€', '🦀'] {
+            let mut b = b"ab".to_vec();
+            let mut enc = [0; 4];
+            b.extend_from_slice(c.encode_utf8(&mut enc).as_bytes());
+            assert_eq!(utf8_boundary(&b), b.len(), "{c:?}");
+            for cut in 1..c.len_utf8() {
+                assert_eq!(utf8_boundary(&b[..2 + cut]), 2, "{c:?} cut after {cut} byte(s)");
+            }
+        }
+        assert_eq!(utf8_boundary(b""), 0);
+        assert_eq!(utf8_boundary(b"abc"), 3);
+    }
+
+    #[test]
+    fn long_line_reassembled() {
+        // Offset by one byte so the limit falls inside multibyte characters.
+        let long = format!("x{}\n", "漢字🦀".repeat(LINE_LIMIT / 5));
+        let text = format!("{long}tail\n");
+        let mut lines = Lines::new(reader(&text));
+        let mut chunks = Vec::new();
+        while let Some(c) = lines.next().unwrap() {
+            chunks.push(c);
+        }
+        let n = chunks.len();
+        assert!(n > 3);
+        assert!(chunks.iter().all(|c| c.text.len() <= LINE_LIMIT));
+        // Only the last chunk of each line ends it.
+        assert!(chunks[..n - 2].iter().all(|c| c.more));
+        assert!(!chunks[n - 2].more && !chunks[n - 1].more);
+        assert_eq!(chunks[n - 1].text, "tail\n");
+        assert_eq!(chunks.iter().map(|c| c.text.as_str()).collect::<String>(), text);
+        assert_eq!(read_lines(reader(&text)).unwrap(), [long.as_str(), "tail\n"]);
+    }
+
+    #[test]
+    fn identical_non_ascii() {
+        let text = format!("héllo\n{}\n€ 🦀\n", "漢字🦀".repeat(LINE_LIMIT / 5));
+        let mut ops = Vec::new();
+        let st = LineDiff::new(reader(&text), reader(&text))
+            .run(|op, _| {
+                ops.push(op);
+                Ok(())
+            })
+            .unwrap();
+        assert!(st.is_empty());
+        assert_eq!(st, DiffStats::default());
+        assert!(ops.iter().all(|&op| op == Op::Equal));
+    }
+
+    fn numbered(r: std::ops::Range<usize>) -> String {
+        r.fold(String::new(), |mut s, i| {
+            let _ = writeln!(s, "{i}");
+            s
+        })
+    }
+
+    fn diff(old: &str, new: &str) -> (DiffStats, String) {
+        let mut out = String::new();
+        let st = LineDiff::new(reader(old), reader(new))
+            .run(|op, l| {
+                out.push(match op {
+                    Op::Equal => ' ',
+                    Op::Delete => '-',
+                    Op::Insert => '+',
+                });
+                out.push_str(l);
+                Ok(())
+            })
+            .unwrap();
+        (st, out)
+    }
+
+    // Applies the diff output, checking it turns |old| into |new|.
+    fn check(old: &str, new: &str, out: &str) {
+        let mut a = String::new();
+        let mut b = String::new();
+        for l in out.split_inclusive('\n') {
+            let (op, l) = l.split_at(1);
+            if op != "+" {
+                a.push_str(l);
+            }
+            if op != "-" {
+                b.push_str(l);
+            }
+        }
+        assert_eq!(a, old);
+        assert_eq!(b, new);
+    }
+
+    #[test]
+    fn insert_at_top_of_long_file() {
+        let old = numbered(0..3 * WINDOW);
+        let new = format!("top\n{old}");
+        let (st, out) = diff(&old, &new);
+        assert_eq!(st, DiffStats { hunks: 1, added: 1, removed: 0 });
+        assert!(out.starts_with("+top\n 0\n"));
+        check(&old, &new, &out);
+    }
+
+    #[test]
+    fn delete_more_than_window() {
+        let old = numbered(0..4 * WINDOW);
+        let new = numbered(0..100) + &numbered(100 + 2 * WINDOW..4 * WINDOW);
+        let (st, out) = diff(&old, &new);
+        assert_eq!(st, DiffStats { hunks: 1, added: 0, removed: 2 * WINDOW });
+        check(&old, &new, &out);
+    }
+
+    #[test]
+    fn one_side_ends_early() {
+        let old = numbered(0..2 * WINDOW + 10);
+        let new = numbered(0..10);
+        let (st, out) = diff(&old, &new);
+        assert_eq!(st, DiffStats { hunks: 1, added: 0, removed: 2 * WINDOW });
+        check(&old, &new, &out);
+
+        let (st, out) = diff(&new, &old);
+        assert_eq!(st, DiffStats { hunks: 1, added: 2 * WINDOW, removed: 0 });
+        check(&new, &old, &out);
+    }
+
+    #[test]
+    fn disjoint() {
+        let old = numbered(0..3 * WINDOW);
+        let new = numbered(0..2 * WINDOW).replace('\n', " new\n");
+        let (st, out) = diff(&old, &new);
+        assert_eq!(st, DiffStats { hunks: 1, added: 2 * WINDOW, removed: 3 * WINDOW });
+        check(&old, &new, &out);
+        assert_eq!(diff("", "").0, DiffStats::default());
+    }
+
+    #[test]
+    fn long_line_changed_in_one_chunk() {
+        let long = "a".repeat(2 * LINE_LIMIT);
+        let old = format!("start\n{long}b\nend\n");
+        let new = format!("start\n{long}c\nend\n");
+        let (st, out) = diff(&old, &new);
+        assert_eq!(st, DiffStats { hunks: 1, added: 1, removed: 1 });
+        assert_eq!(out, format!(" start\n-{long}b\n+{long}c\n end\n"));
+
+        let (st, out) = diff(&old, &old);
+        assert!(st.is_empty());
+        assert_eq!(out.lines().count(), 3);
+    }
+
+    #[test]
+    fn line_at_limit_without_newline() {
+        let text = "a".repeat(LINE_LIMIT);
+        let mut lines = Lines::new(reader(&text));
+        let c = lines.next().unwrap().unwrap();
+        assert!(!c.more);
+        assert_eq!(c.text, text);
+        assert_eq!(lines.next().unwrap(), None);
+    }
+}
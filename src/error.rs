@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+use std::{fmt, io, slice};
+
+use crate::diff::DiffStats;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[must_use]
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Error {
+    /// The actual output differs from the golden file.
+    Mismatch { path: PathBuf, stats: DiffStats, diff: String },
+    /// The golden file does not exist. The diff shows the actual output as
+    /// inserted lines.
+    Missing { path: PathBuf, stats: DiffStats, diff: String },
+    /// A golden or actual file could not be decoded, e.g. corrupt compressed
+    /// data or invalid UTF-8 in a text file.
+    Decode { path: PathBuf, source: io::Error },
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The golden path is absolute or escapes the golden directory.
+    InvalidPath { path: PathBuf },
+    /// A `GoldenWriter` for the path was still open during verification.
+    WriterOpen { path: PathBuf },
+    /// The path was already registered with this `Golden`.
+    Duplicate { path: PathBuf },
+    /// Another live `Golden` in this process already writes the golden file.
+    /// Only detected if both use `Golden::with_process_registry`.
+    Claimed { path: PathBuf },
+    /// `UPDATE_GOLDEN` could not be parsed.
+    InvalidPattern { pattern: String, reason: String },
+    /// `UPDATE_GOLDEN` asked to write golden files while running on CI, as
+    /// detected from environment variable |var|.
+    UpdateOnCi { var: String },
+    /// More than one golden file failed.
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        Self::Io { path: path.to_owned(), source }
+    }
+
+    // Errors while reading a golden file may come from its decoder or, for
+    // text files, from UTF-8 decoding. Those are reported as `Decode`.
+    pub(crate) fn read(path: &Path, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::UnexpectedEof => Self::Decode { path: path.to_owned(), source },
+            _ => Self::io(path, source),
+        }
+    }
+
+    /// The individual errors, flattening `Multiple`.
+    pub fn errors(&self) -> &[Error] {
+        match self {
+            Self::Multiple(errors) => errors,
+            e => slice::from_ref(e),
+        }
+    }
+
+    /// Whether this is only due to golden files being different or missing,
+    /// rather than an infrastructure failure such as I/O.
+    #[must_use]
+    pub fn is_mismatch(&self) -> bool {
+        self.errors().iter().all(|e| matches!(e, Self::Mismatch { .. } | Self::Missing { .. }))
+    }
+
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Mismatch { path, .. }
+            | Self::Missing { path, .. }
+            | Self::Decode { path, .. }
+            | Self::Io { path, .. }
+            | Self::InvalidPath { path }
+            | Self::WriterOpen { path }
+            | Self::Duplicate { path }
+            | Self::Claimed { path } => Some(path),
+            Self::InvalidPattern { .. } | Self::UpdateOnCi { .. } | Self::Multiple(_) => None,
+        }
+    }
+
+    /// The rendered diff, if any.
+    #[must_use]
+    pub fn diff(&self) -> Option<&str> {
+        match self {
+            Self::Mismatch { diff, .. } | Self::Missing { diff, .. } => Some(diff),
+            _ => None,
+        }
+    }
+
+    fn summary(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mismatch { path, stats, .. } => write!(
+                f,
+                "{}: {} difference(s) (+{} -{})",
+                path.display(),
+                stats.hunks,
+                stats.added,
+                stats.removed
+            ),
+            Self::Missing { path, stats, .. } => {
+                write!(f, "{}: missing golden file (+{})", path.display(), stats.added)
+            }
+            Self::Decode { path, source } => {
+                write!(f, "{}: could not decode: {source}", path.display())
+            }
+            Self::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            Self::InvalidPath { path } => write!(
+                f,
+                "{}: invalid golden path, must be relative and not use ..",
+                path.display()
+            ),
+            Self::WriterOpen { path } => {
+                write!(f, "{}: writer is still open; drop or finish it first", path.display())
+            }
+            Self::Duplicate { path } => {
+                write!(f, "{}: golden file registered more than once", path.display())
+            }
+            Self::Claimed { path } => {
+                write!(f, "{}: golden file used by another live Golden", path.display())
+            }
+            Self::InvalidPattern { pattern, reason } => {
+                write!(f, "invalid UPDATE_GOLDEN value {pattern:?}: {reason}")
+            }
+            Self::UpdateOnCi { var } => write!(
+                f,
+                "refusing to update golden files on CI ({var} is set); unset UPDATE_GOLDEN or \
+                 set GOLDEN_ALLOW_CI=1"
+            ),
+            Self::Multiple(errors) => {
+                write!(f, "{} golden file(s) failed:", errors.len())?;
+                for e in errors {
+                    write!(f, "\n  ")?;
+                    e.summary(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary(f)?;
+        let errors = self.errors();
+        if errors.iter().any(|e| matches!(e, Self::Missing { .. })) {
+            write!(f, "\nSet UPDATE_GOLDEN=1 to update golden files or UPDATE_GOLDEN=new to only create missing ones.")?;
+        } else if errors.iter().any(|e| matches!(e, Self::Mismatch { .. })) {
+            write!(f, "\nSet UPDATE_GOLDEN=1 to update golden files.")?;
+        }
+        for diff in errors.iter().filter_map(Error::diff).filter(|d| !d.is_empty()) {
+            write!(f, "\n\n{}", diff.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode { source, .. } | Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
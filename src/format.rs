@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "bzip2")]
+use crate::codec::Bzip2;
+#[cfg(feature = "xz")]
+use crate::codec::Xz;
+#[cfg(feature = "zstd")]
+use crate::codec::Zstd;
+use crate::codec::{Codec, Gzip};
+
+// Extensions that are compared as binary files by default.
+const BINARY_EXTS: &[&str] = &["bin", "gif", "ico", "jpeg", "jpg", "pdf", "png", "wasm", "zip"];
+
+// Decides from the extension of a golden file whether it is binary and how it
+// is encoded.
+#[must_use]
+#[derive(Debug, Clone)]
+pub(crate) struct Formats {
+    pub(crate) binary_exts: Vec<String>,
+    pub(crate) codecs: BTreeMap<String, Arc<dyn Codec>>,
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        let mut codecs: BTreeMap<String, Arc<dyn Codec>> = BTreeMap::new();
+        codecs.insert("gz".to_owned(), Arc::new(Gzip::default()));
+        #[cfg(feature = "zstd")]
+        codecs.insert("zst".to_owned(), Arc::new(Zstd::default()));
+        #[cfg(feature = "xz")]
+        codecs.insert("xz".to_owned(), Arc::new(Xz::default()));
+        #[cfg(feature = "bzip2")]
+        codecs.insert("bz2".to_owned(), Arc::new(Bzip2::default()));
+        Self { binary_exts: BINARY_EXTS.iter().map(|&s| s.to_owned()).collect(), codecs }
+    }
+}
+
+impl Formats {
+    pub(crate) fn codec_for(&self, p: &Path) -> Option<Arc<dyn Codec>> {
+        let ext = p.extension()?.to_str()?;
+        self.codecs.get(ext).cloned()
+    }
+
+    pub(crate) fn is_binary(&self, p: &Path) -> bool {
+        // Look through the codec extension, e.g. "out.bin.gz".
+        let ext = match p.extension() {
+            Some(_) if self.codec_for(p).is_some() => {
+                p.file_stem().map(Path::new).and_then(Path::extension)
+            }
+            ext => ext,
+        };
+        ext.and_then(OsStr::to_str).is_some_and(|ext| self.binary_exts.iter().any(|e| e == ext))
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use colored::Colorize;
+
+use crate::diff::{DiffStats, Op};
+
+// Bytes per hex dump row.
+const ROW: usize = 16;
+// Number of equal rows printed around each differing row.
+const CONTEXT: usize = 2;
+
+fn read_row(r: &mut dyn Read) -> io::Result<Vec<u8>> {
+    let mut row = Vec::with_capacity(ROW);
+    r.take(ROW as u64).read_to_end(&mut row)?;
+    Ok(row)
+}
+
+fn paint(s: &str, op: Op) -> String {
+    match op {
+        Op::Equal => s.to_owned(),
+        Op::Delete => s.red().to_string(),
+        Op::Insert => s.green().to_string(),
+    }
+}
+
+// Prints |row| at offset |off|, highlighting bytes that differ from |other|.
+fn print_row(w: &mut dyn Write, off: usize, row: &[u8], other: &[u8], op: Op) -> io::Result<()> {
+    let sign = match op {
+        Op::Equal => ' ',
+        Op::Delete => '-',
+        Op::Insert => '+',
+    };
+    let mut hex = String::new();
+    let mut ascii = String::new();
+    for i in 0..ROW {
+        if i == ROW / 2 {
+            hex.push(' ');
+        }
+        let Some(&c) = row.get(i) else {
+            hex.push_str("   ");
+            continue;
+        };
+        let op = if other.get(i) == Some(&c) { Op::Equal } else { op };
+        let a = if c.is_ascii_graphic() || c == b' ' { c as char } else { '.' };
+        hex.push_str(&paint(&format!("{c:02x}"), op));
+        hex.push(' ');
+        ascii.push_str(&paint(&a.to_string(), op));
+    }
+    writeln!(w, "{sign}{off:08x}  {hex} |{ascii}|")
+}
+
+// Compares two binary streams row by row and prints a hex dump of differing
+// rows with some surrounding context.
+pub(crate) fn process_hex_diffs(
+    p: &Path,
+    mut golden: Box<dyn Read>,
+    mut actual: Box<dyn Read>,
+    w: &mut dyn Write,
+) -> io::Result<DiffStats> {
+    let mut st = DiffStats::default();
+    let mut ctx: VecDeque<(usize, Vec<u8>)> = VecDeque::new();
+    let mut after = 0;
+    let mut last: Option<usize> = None;
+    let mut in_hunk = false;
+    let mut off = 0;
+    loop {
+        let a = read_row(&mut golden)?;
+        let b = read_row(&mut actual)?;
+        if a.is_empty() && b.is_empty() {
+            break;
+        }
+        if a == b {
+            in_hunk = false;
+            if after > 0 {
+                print_row(w, off, &a, &a, Op::Equal)?;
+                last = Some(off);
+                after -= 1;
+            } else {
+                ctx.push_back((off, a));
+                if ctx.len() > CONTEXT {
+                    ctx.pop_front();
+                }
+            }
+        } else {
+            if !in_hunk {
+                if st.is_empty() {
+                    writeln!(w, "{}", format!("{}:", p.display()).bold())?;
+                }
+                st.hunks += 1;
+                in_hunk = true;
+            }
+            let first = ctx.front().map_or(off, |(o, _)| *o);
+            if last.is_some_and(|l| l + ROW < first) {
+                writeln!(w, "...")?;
+            }
+            for (o, r) in ctx.drain(..) {
+                print_row(w, o, &r, &r, Op::Equal)?;
+            }
+            if !a.is_empty() {
+                print_row(w, off, &a, &b, Op::Delete)?;
+                st.removed += 1;
+            }
+            if !b.is_empty() {
+                print_row(w, off, &b, &a, Op::Insert)?;
+                st.added += 1;
+            }
+            last = Some(off);
+            after = CONTEXT;
+        }
+        off += ROW;
+    }
+    if !st.is_empty() {
+        writeln!(w)?;
+    }
+    Ok(st)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex_diff(golden: &[u8], actual: &[u8]) -> (DiffStats, String) {
+        let mut out = Vec::new();
+        let st = process_hex_diffs(
+            Path::new("x.bin"),
+            Box::new(io::Cursor::new(golden.to_vec())),
+            Box::new(io::Cursor::new(actual.to_vec())),
+            &mut out,
+        )
+        .unwrap();
+        (st, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn differing_row() {
+        let golden: Vec<u8> = (b'a'..=b'z').chain(b'A'..=b'Z').collect();
+        let mut actual = golden.clone();
+        actual[0x11] = 0;
+        let (st, out) = hex_diff(&golden, &actual);
+        assert_eq!(st, DiffStats { hunks: 1, added: 1, removed: 1 });
+        assert_eq!(
+            out,
+            "x.bin:
+ 00000000  61 62 63 64 65 66 67 68  69 6a 6b 6c 6d 6e 6f 70  |abcdefghijklmnop|
+-00000010  71 72 73 74 75 76 77 78  79 7a 41 42 43 44 45 46  |qrstuvwxyzABCDEF|
++00000010  71 00 73 74 75 76 77 78  79 7a 41 42 43 44 45 46  |q.stuvwxyzABCDEF|
+ 00000020  47 48 49 4a 4b 4c 4d 4e  4f 50 51 52 53 54 55 56  |GHIJKLMNOPQRSTUV|
+ 00000030  57 58 59 5a                                       |WXYZ|
+
+"
+        );
+    }
+
+    #[test]
+    fn separate_rows() {
+        let golden = vec![7u8; 16 * ROW];
+        let mut actual = golden.clone();
+        actual[4 * ROW + 5] = 0;
+        actual[12 * ROW] = 0;
+        actual.extend([1, 2, 3]);
+        let (st, out) = hex_diff(&golden, &actual);
+        // The appended partial row is a hunk of its own, printed without a gap
+        // as it is within the context of the previous one.
+        assert_eq!(st, DiffStats { hunks: 3, added: 3, removed: 2 });
+        let offsets: Vec<_> =
+            out.lines().filter(|l| l.starts_with(['-', '+'])).map(|l| &l[..9]).collect();
+        assert_eq!(offsets, ["-00000040", "+00000040", "-000000c0", "+000000c0", "+00000100"]);
+        // Rows between the changes that are not context are elided.
+        assert_eq!(out.lines().filter(|&l| l == "...").count(), 1);
+        assert!(out.contains("\n 00000060 ") && !out.contains("\n 00000070 "));
+        assert!(out.contains("\n 000000a0 ") && !out.contains("\n 00000090 "));
+    }
+
+    #[test]
+    fn equal() {
+        let data = vec![1u8; 100];
+        assert_eq!(hex_diff(&data, &data), (DiffStats::default(), String::new()));
+        assert_eq!(hex_diff(b"", b""), (DiffStats::default(), String::new()));
+    }
+}
@@ -27,201 +27,748 @@
     clippy::cast_sign_loss,
     clippy::items_after_statements,
     clippy::many_single_char_names,
-    clippy::match_on_vec_items,
     clippy::missing_errors_doc,
     clippy::missing_panics_doc,
     clippy::module_name_repetitions,
     clippy::similar_names,
     clippy::struct_excessive_bools,
+    clippy::struct_field_names,
     clippy::too_many_lines,
     clippy::unreadable_literal
 )]
 
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::{Path, PathBuf};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use std::{env, thread};
 
-use colored::Colorize;
-use dissimilar::{diff, Chunk};
-use eyre::{eyre, Result};
-use flate2::bufread::GzDecoder;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use tempfile::{tempdir, TempDir};
+use tempfile::{tempdir, NamedTempFile, TempDir};
+
+#[cfg(feature = "bzip2")]
+pub use crate::codec::Bzip2;
+#[cfg(feature = "xz")]
+pub use crate::codec::Xz;
+#[cfg(feature = "zstd")]
+pub use crate::codec::Zstd;
+pub use crate::codec::{Codec, Encoder, Gzip};
+
+pub use crate::diff::DiffStats;
+use crate::diff::LineDiff;
+pub use crate::error::{Error, Result};
+use crate::format::Formats;
+use crate::hex::process_hex_diffs;
+pub use crate::output::Output;
+pub use crate::pending::Pending;
+use crate::pending::{pending_path, remove_pending};
+use crate::registry::Claims;
+use crate::render::{context_from_env, patch, renderer};
+pub use crate::render::{DiffStyle, Hunk};
+use crate::summary::{write_json, write_summary, Change, Changed};
+use crate::update::{ci_var, Mode, Update};
+pub use crate::writer::GoldenWriter;
+use crate::writer::Tracker;
+
+mod codec;
+mod diff;
+mod error;
+mod format;
+mod hex;
+mod output;
+mod pending;
+mod registry;
+mod render;
+mod summary;
+mod update;
+mod writer;
 
 #[must_use]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-enum CursorOp {
-    Equal,
-    Delete,
-    Insert,
+#[derive(Debug, Clone)]
+struct Entry {
+    path: PathBuf,
+    binary: bool,
+    codec: Option<Arc<dyn Codec>>,
 }
 
 #[must_use]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-struct Cursor<'a> {
-    idx: usize,
-    line: usize,
-    s: &'a str,
-    printing: bool,
+#[derive(Debug)]
+pub struct Golden {
+    golden: PathBuf,
+    tmp: TempDir,
+    paths: Vec<Entry>,
+    formats: Formats,
+    style: DiffStyle,
+    context: usize,
+    output: Output,
+    tracker: Tracker,
+    claims: Option<Claims>,
+    keep: Option<PathBuf>,
+    pending: bool,
+    summary: Option<PathBuf>,
+    allow_ci: bool,
+    finished: bool,
 }
 
-impl<'a> Cursor<'a> {
-    fn new(s: &'a str) -> Self {
-        Self { s, idx: 0, line: 0, printing: false }
+impl Golden {
+    pub fn new(p: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            golden: p.as_ref().to_path_buf(),
+            tmp: tempdir().map_err(|e| Error::io(&env::temp_dir(), e))?,
+            paths: Vec::new(),
+            formats: Formats::default(),
+            style: DiffStyle::from_env(),
+            context: context_from_env(),
+            output: Output::Stdout,
+            tracker: Tracker::default(),
+            claims: None,
+            keep: keep_dir_from_env(),
+            pending: env::var("GOLDEN_PENDING").is_ok_and(|v| v == "1"),
+            summary: env::var_os("GOLDEN_SUMMARY").filter(|v| !v.is_empty()).map(PathBuf::from),
+            allow_ci: env::var("GOLDEN_ALLOW_CI").is_ok_and(|v| v == "1"),
+            finished: false,
+        })
+    }
+
+    /// Where to write rendered diffs. Defaults to standard output.
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.output = output;
+        self
     }
 
-    fn advance(&mut self, l: usize, op: CursorOp, print_equal: bool) {
-        if op != CursorOp::Equal {
-            // Print from beginning of the current line if we haven't already.
-            if !self.printing && print_equal {
-                print!("{}", &self.s[self.line..self.idx]);
-            }
-            self.printing = true;
-            // Print diff.
-            let s = &self.s[self.idx..self.idx + l];
-            if op == CursorOp::Delete {
-                print!("{}", s.red());
-            } else {
-                print!("{}", s.green());
-            }
+    /// Overrides the diff style chosen from `GOLDEN_DIFF` or the terminal.
+    pub fn with_diff_style(mut self, style: DiffStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Number of unchanged lines shown around each unified diff hunk.
+    pub fn with_context(mut self, context: usize) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Compare files with extension |ext| as binary, e.g. "dat".
+    pub fn with_binary_extension(mut self, ext: impl Into<String>) -> Self {
+        self.formats.binary_exts.push(ext.into());
+        self
+    }
+
+    /// Encode and decode files with extension |ext| using |codec|, e.g. to
+    /// change the compression level. Gzip is registered for "gz" by default,
+    /// and zstd, xz and bzip2 for "zst", "xz" and "bz2" if their features are
+    /// enabled.
+    pub fn with_codec(mut self, ext: impl Into<String>, codec: impl Codec + 'static) -> Self {
+        self.formats.codecs.insert(ext.into(), Arc::new(codec));
+        self
+    }
+
+    /// Registers golden files in a process-wide registry, so that two live
+    /// `Golden`s writing the same golden file are an error, e.g. tests that
+    /// share a golden directory. Only `Golden`s using this are checked.
+    pub fn with_process_registry(mut self) -> Self {
+        self.claims.get_or_insert_with(Claims::default);
+        self
+    }
+
+    /// When verification fails, copy the actual output of each mismatching or
+    /// missing golden into |dir|, with a `.patch` file for text goldens that
+    /// have no codec. This can also be enabled with `GOLDEN_KEEP_ACTUAL=1`,
+    /// which uses `target/moldenfile/actual`, or `GOLDEN_KEEP_ACTUAL=<dir>`.
+    pub fn with_keep_actual(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.keep = Some(dir.into());
+        self
+    }
+
+    /// When verification fails, write the actual output of each mismatching or
+    /// missing golden next to it as a `.pending` file, to review with the
+    /// `moldenfile` command. Also enabled by `GOLDEN_PENDING=1`.
+    pub fn with_pending(mut self) -> Self {
+        self.pending = true;
+        self
+    }
+
+    /// When updating, including dry runs with `UPDATE_GOLDEN=diff`, append
+    /// one JSON object per golden file to |p| describing the change, e.g.
+    /// `{"golden":"tests/golden","path":"a.txt","change":"modify","added":3,
+    /// "removed":1,"dry_run":true}`. "change" is one of "create", "modify"
+    /// and "unchanged". Also set by `GOLDEN_SUMMARY=<file>`.
+    pub fn with_summary_file(mut self, p: impl Into<PathBuf>) -> Self {
+        self.summary = Some(p.into());
+        self
+    }
+
+    /// Allow `UPDATE_GOLDEN` to write golden files on CI. Otherwise updating
+    /// or creating goldens fails there, in case `UPDATE_GOLDEN` was left set
+    /// by accident. Also set by `GOLDEN_ALLOW_CI=1`.
+    pub fn with_allow_ci(mut self) -> Self {
+        self.allow_ci = true;
+        self
+    }
+
+    pub fn file(&mut self, p: impl AsRef<Path>) -> Result<GoldenWriter> {
+        let p = p.as_ref();
+        self.write_tmp(p, self.formats.is_binary(p), self.formats.codec_for(p))
+    }
+
+    /// Like |file|, but always compares the file as binary.
+    pub fn binary_file(&mut self, p: impl AsRef<Path>) -> Result<GoldenWriter> {
+        let p = p.as_ref();
+        self.write_tmp(p, true, self.formats.codec_for(p))
+    }
+
+    /// Like |file|, but encodes the file with |codec| regardless of its extension.
+    pub fn file_with_codec(
+        &mut self,
+        p: impl AsRef<Path>,
+        codec: impl Codec + 'static,
+    ) -> Result<GoldenWriter> {
+        let p = p.as_ref();
+        self.write_tmp(p, self.formats.is_binary(p), Some(Arc::new(codec)))
+    }
+
+    // Golden paths must be relative and stay inside the golden directory.
+    // Returns |p| without any "." components.
+    fn validate(p: &Path) -> Result<PathBuf> {
+        let ok = p.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !ok || p.file_name().is_none() {
+            return Err(Error::InvalidPath { path: p.to_owned() });
         }
-        let mut first_newline = l;
-        for i in 0..l {
-            if self.s.as_bytes()[self.idx + i] == b'\n' {
-                if first_newline == l {
-                    first_newline = i;
-                }
-                self.line = self.idx + i + 1;
+        Ok(p.components().filter(|c| matches!(c, Component::Normal(_))).collect())
+    }
+
+    // Each golden file may only be written once, by one `Golden`.
+    fn claim(&mut self, p: &Path) -> Result<()> {
+        if self.paths.iter().any(|e| e.path == p) {
+            return Err(Error::Duplicate { path: p.to_owned() });
+        }
+        let golden = self.golden.join(p);
+        if let Some(claims) = &mut self.claims {
+            if !claims.claim(&golden) {
+                return Err(Error::Claimed { path: p.to_owned() });
             }
         }
-        // Print rest of the line if necessary.
-        if op == CursorOp::Equal && self.printing && print_equal {
-            let en = if first_newline == l {
-                self.idx + l
-            } else {
-                self.printing = false;
-                self.idx + first_newline + 1
-            };
-            print!("{}", &self.s[self.idx..en]);
+        Ok(())
+    }
+
+    fn write_tmp(
+        &mut self,
+        p: &Path,
+        binary: bool,
+        codec: Option<Arc<dyn Codec>>,
+    ) -> Result<GoldenWriter> {
+        let p = &Self::validate(p)?;
+        self.claim(p)?;
+        let tmp = self.tmp.path().join(p);
+        if let Some(parent) = tmp.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
         }
-        self.idx += l;
+        let f = BufWriter::new(File::create(&tmp).map_err(|e| Error::io(&tmp, e))?);
+        let inner: Box<dyn Encoder> = match &codec {
+            Some(codec) => codec.encoder(Box::new(f)).map_err(|e| Error::io(&tmp, e))?,
+            None => Box::new(f),
+        };
+        self.paths.push(Entry { path: p.to_owned(), binary, codec });
+        Ok(GoldenWriter::new(p, inner, self.tracker.clone()))
     }
-}
 
-#[must_use]
-#[derive(Debug)]
-pub struct Golden {
-    golden: PathBuf,
-    tmp: TempDir,
-    paths: Vec<PathBuf>,
-}
+    fn read(e: &Entry, p: &Path) -> io::Result<Box<dyn Read>> {
+        let f = Box::new(BufReader::new(File::open(p)?));
+        match &e.codec {
+            Some(codec) => codec.decoder(f),
+            None => Ok(f),
+        }
+    }
 
-const BYTE_LIMIT: u64 = 1024;
+    // Opens the decoded golden and actual output for |e|.
+    fn open(&self, e: &Entry, missing: bool) -> Result<(Box<dyn Read>, Box<dyn Read>)> {
+        // A missing golden is shown as if all of the actual output was inserted.
+        let golden: Box<dyn Read> = if missing {
+            Box::new(io::empty())
+        } else {
+            let p = self.golden.join(&e.path);
+            Self::read(e, &p).map_err(|err| Error::io(&p, err))?
+        };
+        let p = self.tmp.path().join(&e.path);
+        let actual = Self::read(e, &p).map_err(|err| Error::io(&p, err))?;
+        Ok((golden, actual))
+    }
 
-impl Golden {
-    pub fn new(p: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self { golden: p.as_ref().to_path_buf(), tmp: tempdir()?, paths: Vec::new() })
+    // The path of the golden for |e| relative to the current directory, as
+    // shown in diffs, so that unified diffs apply with `patch -p1`. Relative
+    // golden directories keep their `..` components and absolute ones outside
+    // the current directory are shown in full.
+    fn shown_path(&self, e: &Entry) -> PathBuf {
+        let golden: PathBuf = if self.golden.is_absolute() {
+            let rel = env::current_dir().ok().and_then(|d| self.golden.strip_prefix(d).ok());
+            rel.unwrap_or(&self.golden).to_path_buf()
+        } else {
+            self.golden.components().filter(|c| *c != Component::CurDir).collect()
+        };
+        golden.join(&e.path)
     }
 
-    pub fn file(&mut self, p: impl AsRef<Path>) -> Result<Box<dyn Write>> {
-        self.write_tmp(p.as_ref())
+    fn compare(&self, e: &Entry, missing: bool, w: &mut dyn Write) -> Result<DiffStats> {
+        let (golden, actual) = self.open(e, missing)?;
+        render(e, &self.shown_path(e), self.style, self.context, golden, actual, w)
+            .map_err(|err| Error::read(&e.path, err))
     }
 
-    fn write_tmp(&mut self, p: &Path) -> Result<Box<dyn Write>> {
-        self.paths.push(p.to_owned());
-        let f = BufWriter::new(File::create(self.tmp.path().join(p))?);
-        if p.extension().unwrap_or_default() == "gz" {
-            Ok(Box::new(GzEncoder::new(f, Compression::best())))
-        } else {
-            Ok(Box::new(f))
+    fn is_missing(&self, e: &Entry) -> bool {
+        !self.golden.join(&e.path).exists()
+    }
+
+    // Checks that the output for |p| was completely written.
+    fn check_written(&self, p: &Path) -> Result<()> {
+        if self.tracker.is_open(p) {
+            return Err(Error::WriterOpen { path: p.to_owned() });
+        }
+        if let Some(err) = self.tracker.error(p) {
+            return Err(Error::io(p, err));
         }
+        Ok(())
     }
 
-    fn read(p: &Path) -> Result<Box<dyn Read>> {
-        let f = BufReader::new(File::open(p)?);
-        if p.extension().unwrap_or_default() == "gz" {
-            Ok(Box::new(GzDecoder::new(f)))
+    // Compares one golden file, writing the rendered diff to |out|.
+    fn check(&self, e: &Entry, out: &mut Vec<u8>) -> Result<()> {
+        self.check_written(&e.path)?;
+        let missing = self.is_missing(e);
+        let start = out.len();
+        let stats = self.compare(e, missing, out)?;
+        let diff = String::from_utf8_lossy(&out[start..]).into_owned();
+        let path = e.path.clone();
+        if missing {
+            Err(Error::Missing { path, stats, diff })
+        } else if !stats.is_empty() {
+            Err(Error::Mismatch { path, stats, diff })
         } else {
-            Ok(Box::new(f))
+            Ok(())
+        }
+    }
+
+    // Copies the actual output for |e| into |dir|, along with a patch that
+    // updates the golden when applied from the current directory. Binary and
+    // encoded files get no patch, as it would only apply to decoded content.
+    fn keep_actual(&self, e: &Entry, dir: &Path) -> Result<()> {
+        let rel = self.shown_path(e);
+        // Keep the copy inside |dir| even if the golden is outside the
+        // current directory.
+        let dst: PathBuf = rel.components().filter(|c| matches!(c, Component::Normal(_))).collect();
+        let dst = dir.join(dst);
+        let parent = dst.parent().unwrap_or(dir);
+        fs::create_dir_all(parent).map_err(|err| Error::io(parent, err))?;
+        fs::copy(self.tmp.path().join(&e.path), &dst).map_err(|err| Error::io(&dst, err))?;
+        if e.binary || e.codec.is_some() {
+            return Ok(());
         }
+        let (golden, actual) = self.open(e, self.is_missing(e))?;
+        let mut out = Vec::new();
+        {
+            let mut r = patch(&rel, self.context, &mut out);
+            LineDiff::new(golden, actual)
+                .run(|op, l| r.op(op, l))
+                .and_then(|_| r.finish())
+                .map_err(|err| Error::read(&e.path, err))?;
+        }
+        let mut name = dst.into_os_string();
+        name.push(".patch");
+        fs::write(&name, out).map_err(|err| Error::io(Path::new(&name), err))
     }
 
-    fn process_diffs(old: &str, new: &str) -> usize {
-        let chunks = diff(old, new);
-        let mut okay_count = 0;
-        let mut old = Cursor::new(old);
-        let mut new = Cursor::new(new);
-        for chunk in &chunks {
-            match chunk {
-                Chunk::Equal(s) => {
-                    old.advance(s.len(), CursorOp::Equal, true);
-                    new.advance(s.len(), CursorOp::Equal, false); // Don't double print for equal chunks.
-                    okay_count += 1;
+    fn verify(&mut self, entries: &[Entry]) -> Vec<Error> {
+        let mut out = Vec::new();
+        let mut errors = Vec::new();
+        let mut pending = 0;
+        for e in entries {
+            let Err(err) = self.check(e, &mut out) else {
+                if self.pending {
+                    errors.extend(remove_pending(&self.golden.join(&e.path)).err());
+                }
+                continue;
+            };
+            if let Some(dir) = self.keep.as_deref().filter(|_| err.is_mismatch()) {
+                errors.extend(self.keep_actual(e, dir).err());
+            }
+            if self.pending && err.is_mismatch() {
+                match self.write_pending(&e.path) {
+                    Ok(()) => pending += 1,
+                    Err(err) => errors.push(err),
                 }
-                Chunk::Delete(s) => old.advance(s.len(), CursorOp::Delete, true),
-                Chunk::Insert(s) => new.advance(s.len(), CursorOp::Insert, false),
             }
+            errors.push(err);
         }
-        let num = chunks.len() - okay_count;
-        if num != 0 {
-            println!();
+        if pending > 0 {
+            let _ = writeln!(
+                out,
+                "wrote {pending} pending golden file(s), review them with `moldenfile review`"
+            );
         }
-        num
+        if let Err(e) = self.output.write(&out) {
+            errors.push(Error::io(Path::new("<output>"), e));
+        }
+        errors
     }
 
-    fn verify(&self) -> Result<()> {
-        for p in &self.paths {
-            let mut golden = Self::read(&self.golden.join(p))?;
-            let mut actual = Self::read(&self.tmp.path().join(p))?;
+    fn write_golden(&self, p: &Path) -> Result<()> {
+        self.install(p, &self.golden.join(p))
+    }
 
-            // Process in chunks of |BYTE_LIMIT|.
-            loop {
-                let mut old = String::new();
-                let mut new = String::new();
-                let mut golden_lim = golden.take(BYTE_LIMIT);
-                let mut actual_lim = actual.take(BYTE_LIMIT);
-                golden_lim.read_to_string(&mut old)?;
-                actual_lim.read_to_string(&mut new)?;
-                golden = golden_lim.into_inner();
-                actual = actual_lim.into_inner();
+    fn write_pending(&self, p: &Path) -> Result<()> {
+        self.install(p, &pending_path(&self.golden.join(p)))
+    }
 
-                if old.is_empty() && new.is_empty() {
-                    break;
-                }
+    // Copies the actual output for |p| to a sibling temporary file of |dst| and
+    // renames it into place, so a killed process never leaves a half-written
+    // golden behind.
+    fn install(&self, p: &Path, dst: &Path) -> Result<()> {
+        let parent = dst.parent().unwrap_or(&self.golden);
+        fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        let tmp = NamedTempFile::new_in(parent).map_err(|e| Error::io(parent, e))?;
+        // Copying also gives the golden normal permissions instead of the
+        // temporary file's private ones.
+        fs::copy(self.tmp.path().join(p), tmp.path()).map_err(|e| Error::io(tmp.path(), e))?;
+        tmp.persist(dst).map_err(|e| Error::io(dst, e.error))?;
+        Ok(())
+    }
+
+    // Takes an exclusive advisory lock on the golden directory, so parallel
+    // test processes don't update the same goldens at once. The lock is
+    // released when the returned file is closed. The lock file lives in the
+    // temp dir, keyed by the canonical golden path, to keep it out of the
+    // golden tree.
+    fn lock(&self) -> Result<File> {
+        fs::create_dir_all(&self.golden).map_err(|e| Error::io(&self.golden, e))?;
+        let golden = self.golden.canonicalize().map_err(|e| Error::io(&self.golden, e))?;
+        let mut hasher = DefaultHasher::new();
+        golden.hash(&mut hasher);
+        let dir = env::temp_dir().join("moldenfile-locks");
+        fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        let p = dir.join(format!("{:016x}.lock", hasher.finish()));
+        let f = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&p)
+            .map_err(|e| Error::io(&p, e))?;
+        f.lock().map_err(|e| Error::io(&p, e))?;
+        Ok(f)
+    }
+
+    // Whether the decoded golden and actual output are identical. Goldens that
+    // can't be read, e.g. because they are corrupt, count as changed.
+    fn is_unchanged(&self, e: &Entry) -> bool {
+        let golden = Self::read(e, &self.golden.join(&e.path));
+        let actual = Self::read(e, &self.tmp.path().join(&e.path));
+        match (golden, actual) {
+            (Ok(golden), Ok(actual)) => same_content(golden, actual).unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    fn change(&self, e: &Entry) -> Change {
+        if self.is_missing(e) {
+            Change::Created
+        } else if self.is_unchanged(e) {
+            Change::Unchanged
+        } else {
+            Change::Modified
+        }
+    }
+
+    // Writes the golden for |e| if its content changed, unless |dry_run|.
+    fn update_one<'a>(&self, e: &'a Entry, dry_run: bool) -> Result<Changed<'a>> {
+        self.check_written(&e.path)?;
+        let change = self.change(e);
+        let stats = match change {
+            Change::Unchanged => None,
+            _ => self
+                .open(e, change == Change::Created)
+                .ok()
+                .and_then(|(golden, actual)| diff_stats(e, golden, actual).ok()),
+        };
+        if !dry_run {
+            remove_pending(&self.golden.join(&e.path))?;
+            if change != Change::Unchanged {
+                self.write_golden(&e.path)?;
+            }
+        }
+        Ok(Changed { path: &e.path, change, stats })
+    }
+
+    // Appends the changes as JSON lines to the summary file, if there is one.
+    fn write_summary_file(&self, changes: &[Changed<'_>], dry_run: bool) -> Result<()> {
+        let Some(p) = &self.summary else {
+            return Ok(());
+        };
+        let mut out = Vec::new();
+        write_json(&mut out, &self.golden, changes, dry_run).map_err(|e| Error::io(p, e))?;
+        if let Some(parent) = p.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        // One write, so lines from parallel test processes don't interleave.
+        File::options()
+            .create(true)
+            .append(true)
+            .open(p)
+            .and_then(|mut f| f.write_all(&out))
+            .map_err(|e| Error::io(p, e))
+    }
+
+    fn update_all(&mut self, entries: &[Entry], dry_run: bool) -> Vec<Error> {
+        let _lock = if dry_run {
+            None
+        } else {
+            match self.lock() {
+                Ok(lock) => Some(lock),
+                Err(e) => return vec![e],
+            }
+        };
+        let mut errors = Vec::new();
+        let mut changes = Vec::new();
+        for e in entries {
+            match self.update_one(e, dry_run) {
+                Ok(change) => changes.push(change),
+                Err(err) => errors.push(err),
+            }
+        }
+        errors.extend(self.write_summary_file(&changes, dry_run).err());
+        let mut out = Vec::new();
+        let r = write_summary(&mut out, &self.golden, &changes, dry_run)
+            .and_then(|()| self.output.write(&out));
+        if let Err(e) = r {
+            errors.push(Error::io(Path::new("<output>"), e));
+        }
+        errors
+    }
 
-                let num = Self::process_diffs(&old, &new);
-                if num != 0 {
-                    return Err(eyre!(
-                        "Found at least {} difference(s) in {}! Set UPDATE_GOLDEN=1 to update golden files.",
-                        num,
-                        p.display()
-                    ));
+    fn run(&mut self) -> Result<()> {
+        self.finished = true;
+        let update = Update::from_env()?;
+        if update.writes() && !self.allow_ci {
+            if let Some(var) = ci_var() {
+                return Err(Error::UpdateOnCi { var: var.to_owned() });
+            }
+        }
+        let paths = self.paths.clone();
+        let (selected, rest): (Vec<_>, Vec<_>) =
+            paths.iter().cloned().partition(|e| update.matches(&e.path));
+        let mut errors = Vec::new();
+        match update.mode {
+            Mode::Verify => errors.extend(self.verify(&paths)),
+            Mode::Update => {
+                if !selected.is_empty() {
+                    errors.extend(self.update_all(&selected, false));
                 }
+                errors.extend(self.verify(&rest));
+            }
+            // A dry run still fails on mismatches, like a --check flag.
+            Mode::DryRun => {
+                if !selected.is_empty() {
+                    errors.extend(self.update_all(&selected, true));
+                }
+                errors.extend(self.verify(&paths));
+            }
+            Mode::CreateMissing => {
+                let missing: Vec<_> = selected.into_iter().filter(|e| self.is_missing(e)).collect();
+                if !missing.is_empty() {
+                    errors.extend(self.update_all(&missing, false));
+                }
+                errors.extend(self.verify(&paths));
             }
         }
-        Ok(())
+        collect(errors)
     }
 
-    fn update(&self) -> Result<()> {
-        for p in &self.paths {
-            fs::copy(self.tmp.path().join(p), self.golden.join(p))?;
+    /// Verifies (or updates, depending on `UPDATE_GOLDEN`) all golden files.
+    /// `UPDATE_GOLDEN` is a comma separated list of a mode, "1" or "all" to
+    /// update, "new" to only create missing goldens or "diff" to print what
+    /// would be updated while still failing on mismatches, and glob patterns
+    /// over golden paths, e.g. `UPDATE_GOLDEN='parser/**'`. Only matching
+    /// goldens are updated and the rest are verified.
+    /// If this is not called, the same happens when the `Golden` is dropped,
+    /// but failures can only be reported by panicking.
+    pub fn finish(mut self) -> Result<()> {
+        self.run()
+    }
+}
+
+// Writes the diff between |golden| and |actual| for |e| to |w|, naming the
+// file |p|.
+fn render(
+    e: &Entry,
+    p: &Path,
+    style: DiffStyle,
+    context: usize,
+    golden: Box<dyn Read>,
+    actual: Box<dyn Read>,
+    w: &mut dyn Write,
+) -> io::Result<DiffStats> {
+    if e.binary {
+        return process_hex_diffs(p, golden, actual, w);
+    }
+    let mut r = renderer(style, p, e.codec.is_some(), context, w);
+    let stats = LineDiff::new(golden, actual).run(|op, l| r.op(op, l))?;
+    r.finish()?;
+    Ok(stats)
+}
+
+// Like |render|, but only counts the changes. This avoids the cost of
+// rendering large diffs.
+fn diff_stats(e: &Entry, golden: Box<dyn Read>, actual: Box<dyn Read>) -> io::Result<DiffStats> {
+    if e.binary {
+        return process_hex_diffs(&e.path, golden, actual, &mut io::sink());
+    }
+    LineDiff::new(golden, actual).run(|_, _| Ok(()))
+}
+
+// GOLDEN_KEEP_ACTUAL is either 1 for the default directory under the cargo
+// target directory, or a directory to keep actual outputs in.
+fn keep_dir_from_env() -> Option<PathBuf> {
+    match env::var_os("GOLDEN_KEEP_ACTUAL") {
+        Some(v) if v == "1" => {
+            let target = env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into());
+            Some(Path::new(&target).join("moldenfile").join("actual"))
         }
-        Ok(())
+        Some(v) if !v.is_empty() && v != "0" => Some(v.into()),
+        _ => None,
+    }
+}
+
+// Compares two streams chunk by chunk.
+fn same_content(mut a: Box<dyn Read>, mut b: Box<dyn Read>) -> io::Result<bool> {
+    const CHUNK: u64 = 64 * 1024;
+    loop {
+        let (mut x, mut y) = (Vec::new(), Vec::new());
+        a.by_ref().take(CHUNK).read_to_end(&mut x)?;
+        b.by_ref().take(CHUNK).read_to_end(&mut y)?;
+        if x != y {
+            return Ok(false);
+        }
+        if x.is_empty() {
+            return Ok(true);
+        }
+    }
+}
+
+// Combines the errors for individual files into one.
+fn collect(mut errors: Vec<Error>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Multiple(errors)),
     }
 }
 
 impl Drop for Golden {
     fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
         if thread::panicking() {
+            eprintln!(
+                "warning: skipped verifying golden files in {} because the thread panicked",
+                self.golden.display()
+            );
             return;
         }
-        let r = env::var("UPDATE_GOLDEN");
-        if r.is_ok() && r.unwrap() == "1" {
-            self.update().expect("could not update golden files");
-        } else {
-            self.verify().expect("could not verify golden files");
+        eprintln!(
+            "warning: Golden for {} dropped without calling finish(), verifying on drop",
+            self.golden.display()
+        );
+        if let Err(e) = self.run() {
+            panic!("{e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_paths() {
+        for (p, want) in
+            [("a.txt", "a.txt"), ("sub/dir/a.txt", "sub/dir/a.txt"), ("./sub/./a.txt", "sub/a.txt")]
+        {
+            assert_eq!(Golden::validate(Path::new(p)).unwrap(), Path::new(want));
+        }
+        let abs = if cfg!(windows) { r"C:\a.txt" } else { "/a.txt" };
+        for p in ["", ".", "..", "../a.txt", "sub/../a.txt", "sub/..", abs] {
+            assert!(
+                matches!(Golden::validate(Path::new(p)), Err(Error::InvalidPath { .. })),
+                "{p:?} should be rejected"
+            );
         }
     }
+
+    #[test]
+    fn invalid_file() {
+        let dir = tempdir().unwrap();
+        let mut g = Golden::new(dir.path()).unwrap().with_output(Output::Silent);
+        assert!(matches!(g.file("../escape.txt"), Err(Error::InvalidPath { .. })));
+        assert!(matches!(g.file(""), Err(Error::InvalidPath { .. })));
+        g.finish().unwrap();
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn duplicate_path() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        let mut g = Golden::new(dir.path()).unwrap().with_output(Output::Silent);
+        drop(g.file("a.txt").unwrap());
+        assert!(matches!(g.file("a.txt"), Err(Error::Duplicate { .. })));
+        assert!(matches!(g.file("./a.txt"), Err(Error::Duplicate { .. })));
+        g.finish().unwrap();
+    }
+
+    #[test]
+    fn registry_conflict() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        let new = || Golden::new(dir.path()).unwrap().with_output(Output::Silent);
+        let mut g1 = new().with_process_registry();
+        let mut g2 = new().with_process_registry();
+        drop(g1.file("a.txt").unwrap());
+        assert!(matches!(g2.file("a.txt"), Err(Error::Claimed { .. })));
+        // Only `Golden`s using the registry are checked.
+        let mut g3 = new();
+        drop(g3.file("a.txt").unwrap());
+        g3.finish().unwrap();
+        // The claim is released with the `Golden` that made it.
+        g1.finish().unwrap();
+        drop(g2.file("a.txt").unwrap());
+        g2.finish().unwrap();
+    }
+
+    #[test]
+    fn missing_golden() {
+        let dir = tempdir().unwrap();
+        let mut g = Golden::new(dir.path())
+            .unwrap()
+            .with_output(Output::Silent)
+            .with_diff_style(DiffStyle::Unified);
+        g.file("sub/a.txt").unwrap().write_all(b"one\ntwo\n").unwrap();
+        g.file("b.bin").unwrap().write_all(&[0; 20]).unwrap();
+        let err = g.finish().unwrap_err();
+        assert!(err.is_mismatch());
+        let stats: Vec<_> = err
+            .errors()
+            .iter()
+            .map(|e| match e {
+                Error::Missing { path, stats, .. } => (path.clone(), *stats),
+                e => panic!("unexpected error {e}"),
+            })
+            .collect();
+        assert_eq!(
+            stats,
+            [
+                (PathBuf::from("sub/a.txt"), DiffStats { hunks: 1, added: 2, removed: 0 }),
+                (PathBuf::from("b.bin"), DiffStats { hunks: 1, added: 2, removed: 0 }),
+            ]
+        );
+        assert!(err.errors()[0].diff().unwrap().contains("@@ -0,0 +1,2 @@\n+one\n+two\n"));
+        // Verifying doesn't create goldens.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
 }
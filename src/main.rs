@@ -0,0 +1,272 @@
+#![warn(
+    clippy::all,
+    clippy::pedantic,
+    future_incompatible,
+    macro_use_extern_crate,
+    meta_variable_misuse,
+    missing_abi,
+    nonstandard_style,
+    noop_method_call,
+    rust_2018_compatibility,
+    rust_2018_idioms,
+    rust_2021_compatibility,
+    trivial_casts,
+    unreachable_pub,
+    unsafe_code,
+    unsafe_op_in_unsafe_fn,
+    unused_import_braces,
+    unused_lifetimes,
+    unused_qualifications,
+    unused
+)]
+
+use std::io::{self, BufRead, Write};
+use std::process::{Command, ExitCode};
+use std::{env, fs};
+
+use moldenfile::{Error, Hunk, Pending, Result};
+
+const USAGE: &str = "usage: moldenfile <list|review [-p]|accept|reject> [PATH...]
+
+Works on the pending golden files written by tests run with GOLDEN_PENDING=1.
+Each PATH is a directory to search, a golden file or a pending file. Defaults
+to the current directory.
+
+  list    list pending files and their number of changes
+  review  show the diff for each pending file and ask whether to accept it.
+          With -p, ask for each hunk instead, and allow editing it
+  accept  replace golden files with their pending files
+  reject  delete pending files";
+
+fn find(paths: &[String]) -> Result<Vec<Pending>> {
+    if paths.is_empty() {
+        return Pending::find(".");
+    }
+    let mut found = Vec::new();
+    for p in paths {
+        found.extend(Pending::find(p)?);
+    }
+    found.sort();
+    found.dedup();
+    Ok(found)
+}
+
+// Fails with all of |errors|, if there are any. Commands keep going after a
+// file fails and report the failures at the end.
+fn collect(mut errors: Vec<Error>) -> Result<()> {
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Multiple(errors)),
+    }
+}
+
+fn list(pending: Vec<Pending>) -> Result<()> {
+    let mut errors = Vec::new();
+    for p in pending {
+        let stats = match p.stats() {
+            Ok(stats) => stats,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if p.is_new() {
+            println!("{}: new (+{})", p.golden().display(), stats.added);
+        } else {
+            println!("{}: +{} -{}", p.golden().display(), stats.added, stats.removed);
+        }
+    }
+    collect(errors)
+}
+
+// Asks |question| until one of the single letter |answers| is given. Returns
+// None at the end of input. |help| explains the answers.
+fn ask(question: &str, answers: &str, help: &str) -> io::Result<Option<char>> {
+    let mut stdin = io::stdin().lock();
+    loop {
+        print!("{question} [{answers}]? ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let mut chars = line.trim().chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if answers.contains(c) {
+                return Ok(Some(c));
+            }
+        }
+        println!("{help}");
+    }
+}
+
+fn review(pending: Vec<Pending>) -> Result<()> {
+    let total = pending.len();
+    let mut errors = Vec::new();
+    for (i, p) in pending.into_iter().enumerate() {
+        let mut out = Vec::new();
+        let stats = match p.diff(&mut out) {
+            Ok(stats) => stats,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        print!("{}", String::from_utf8_lossy(&out));
+        let name = p.golden().display().to_string();
+        let question = format!("({}/{total}) {name} +{} -{}", i + 1, stats.added, stats.removed);
+        let help = "a: accept, r: reject, s: skip, q: quit";
+        let answer = ask(&question, "arsq", help).map_err(term_error)?;
+        let res = match answer {
+            Some('a') => p.accept(),
+            Some('r') => p.reject(),
+            Some('s') => Ok(()),
+            _ => break,
+        };
+        errors.extend(res.err());
+    }
+    collect(errors)
+}
+
+fn term_error(e: io::Error) -> Error {
+    Error::Io { path: "<terminal>".into(), source: e }
+}
+
+// Opens |hunk| in the editor and returns the edited hunk. Returns None if the
+// edit was aborted by emptying the file.
+fn edit(hunk: &Hunk) -> io::Result<Option<Hunk>> {
+    let mut f = tempfile::Builder::new().suffix(".diff").tempfile()?;
+    write!(f, "{hunk}")?;
+    writeln!(f, "# Edit the added lines, or turn a removed line into a context line")?;
+    writeln!(f, "# by replacing its '-' with ' '. Empty the file to abort the edit.")?;
+    f.flush()?;
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or("vi".to_owned());
+    let mut args = editor.split_whitespace();
+    let status = Command::new(args.next().unwrap_or("vi")).args(args).arg(f.path()).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("editor exited with {status}")));
+    }
+    let text = fs::read_to_string(f.path())?;
+    if text.lines().all(|l| l.starts_with('#') || l.trim().is_empty()) {
+        return Ok(None);
+    }
+    hunk.edit(&text).map(Some)
+}
+
+// Asks about each hunk of |p|. Returns the hunks to accept and whether all of
+// them were accepted unchanged, or None to quit.
+fn review_hunks(p: &Pending) -> Result<Option<(Vec<Hunk>, bool)>> {
+    let hunks = p.hunks()?;
+    let mut accepted = Vec::new();
+    let mut all = true;
+    let mut rest = None;
+    let help = "y: accept this hunk, n: reject this hunk, e: edit this hunk, \
+                a: accept this and later hunks in the file, d: reject this and later hunks in the \
+                file, q: quit, leaving this and later files pending";
+    println!("{}", p.golden().display());
+    for (i, hunk) in hunks.iter().enumerate() {
+        loop {
+            let answer = if let Some(answer) = rest {
+                answer
+            } else {
+                hunk.write(&mut io::stdout(), true).map_err(term_error)?;
+                let question = format!("({}/{}) accept this hunk", i + 1, hunks.len());
+                ask(&question, "ynaedq", help).map_err(term_error)?.unwrap_or('q')
+            };
+            match answer {
+                'y' | 'a' => accepted.push(hunk.clone()),
+                'n' | 'd' => all = false,
+                'e' => match edit(hunk) {
+                    Ok(Some(edited)) => {
+                        all &= edited == *hunk;
+                        accepted.push(edited);
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("error: {e}");
+                        continue;
+                    }
+                },
+                _ => return Ok(None),
+            }
+            if matches!(answer, 'a' | 'd') {
+                rest = Some(answer);
+            }
+            break;
+        }
+    }
+    Ok(Some((accepted, all)))
+}
+
+fn review_patch(pending: Vec<Pending>) -> Result<()> {
+    let mut errors = Vec::new();
+    for p in pending {
+        if p.is_binary() {
+            errors.extend(review(vec![p]).err());
+            continue;
+        }
+        let (accepted, all) = match review_hunks(&p) {
+            Ok(Some(answer)) => answer,
+            Ok(None) => break,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let res = if all {
+            p.accept()
+        } else if accepted.is_empty() {
+            p.reject()
+        } else {
+            p.accept_hunks(&accepted)
+        };
+        errors.extend(res.err());
+    }
+    collect(errors)
+}
+
+fn run(args: &[String]) -> Result<bool> {
+    let Some((cmd, mut paths)) = args.split_first() else {
+        return Ok(false);
+    };
+    let patch = cmd == "review" && paths.first().is_some_and(|p| p == "-p");
+    if patch {
+        paths = &paths[1..];
+    }
+    let pending = find(paths)?;
+    match cmd.as_str() {
+        "list" => list(pending)?,
+        "review" if patch => review_patch(pending)?,
+        "review" => review(pending)?,
+        "accept" => {
+            for p in pending {
+                println!("accepted {}", p.golden().display());
+                p.accept()?;
+            }
+        }
+        "reject" => {
+            for p in pending {
+                println!("rejected {}", p.golden().display());
+                p.reject()?;
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
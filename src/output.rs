@@ -0,0 +1,41 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// Where rendered diffs are written. Regardless of the output, the rendered
+/// diff is also included in the error returned for mismatching files.
+#[must_use]
+pub enum Output {
+    /// Standard output, via `print!` so the test harness can capture it.
+    Stdout,
+    /// Standard error, via `eprint!`.
+    Stderr,
+    /// Don't write the diff anywhere.
+    Silent,
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Output {
+    pub(crate) fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Stdout => print!("{}", String::from_utf8_lossy(buf)),
+            Self::Stderr => eprint!("{}", String::from_utf8_lossy(buf)),
+            Self::Silent => {}
+            Self::Writer(w) => {
+                w.write_all(buf)?;
+                w.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdout => write!(f, "Stdout"),
+            Self::Stderr => write!(f, "Stderr"),
+            Self::Silent => write!(f, "Silent"),
+            Self::Writer(_) => write!(f, "Writer(..)"),
+        }
+    }
+}
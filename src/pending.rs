@@ -0,0 +1,230 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+
+use tempfile::NamedTempFile;
+
+use crate::codec::Encoder;
+use crate::diff::{read_lines, DiffStats, LineDiff};
+use crate::error::{Error, Result};
+use crate::format::Formats;
+use crate::render::{apply_hunks, context_from_env, DiffStyle, Hunk, Hunker};
+use crate::{diff_stats, render, Entry, Golden};
+
+const EXT: &str = "pending";
+
+// The pending file for golden file |p|, e.g. "out.txt.pending".
+pub(crate) fn pending_path(p: &Path) -> PathBuf {
+    let mut name = OsString::from(p);
+    name.push(".");
+    name.push(EXT);
+    name.into()
+}
+
+// Removes a stale pending file for golden file |p|, if there is one.
+pub(crate) fn remove_pending(p: &Path) -> Result<()> {
+    let pending = pending_path(p);
+    match fs::remove_file(&pending) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(&pending, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Actual output that did not match its golden file, written next to it by a
+/// `Golden` with pending files enabled. Pending files can be reviewed and then
+/// accepted, replacing the golden, or rejected.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pending {
+    golden: PathBuf,
+}
+
+impl Pending {
+    /// Finds pending files under |p|. |p| may be a directory, which is
+    /// searched recursively skipping hidden and `target` directories, a
+    /// golden file or a pending file.
+    pub fn find(p: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let p = p.as_ref();
+        let mut found = Vec::new();
+        if p.is_dir() {
+            find_in(p, &mut found)?;
+        } else if p.extension().is_some_and(|ext| ext == EXT) {
+            found.push(Self { golden: p.with_extension("") });
+        } else if pending_path(p).exists() {
+            found.push(Self { golden: p.to_owned() });
+        } else {
+            let e = io::Error::new(io::ErrorKind::NotFound, "no pending golden file");
+            return Err(Error::io(p, e));
+        }
+        for pending in &mut found {
+            // Show paths found under "." relative to it.
+            if let Ok(p) = pending.golden.strip_prefix(".") {
+                pending.golden = p.to_owned();
+            }
+        }
+        found.sort();
+        Ok(found)
+    }
+
+    /// The golden file this replaces.
+    #[must_use]
+    pub fn golden(&self) -> &Path {
+        &self.golden
+    }
+
+    /// The pending file itself.
+    #[must_use]
+    pub fn path(&self) -> PathBuf {
+        pending_path(&self.golden)
+    }
+
+    /// Whether there is no golden file yet.
+    #[must_use]
+    pub fn is_new(&self) -> bool {
+        !self.golden.exists()
+    }
+
+    /// Whether the golden is compared as binary, in which case it can't be
+    /// split into hunks.
+    #[must_use]
+    pub fn is_binary(&self) -> bool {
+        self.entry().binary
+    }
+
+    // Only the default codecs and binary extensions are known, so goldens that
+    // don't decode to text with them, e.g. ones written with a custom codec or
+    // binary extension, are compared as raw binary.
+    fn entry(&self) -> Entry {
+        let formats = Formats::default();
+        let e = Entry {
+            path: self.golden.clone(),
+            binary: formats.is_binary(&self.golden),
+            codec: formats.codec_for(&self.golden),
+        };
+        if e.binary || self.is_text(&e) {
+            e
+        } else {
+            Entry { binary: true, codec: None, ..e }
+        }
+    }
+
+    // Whether the golden and pending file both decode to UTF-8 text.
+    fn is_text(&self, e: &Entry) -> bool {
+        let text = |p: &Path| {
+            let mut buf = Vec::new();
+            Golden::read(e, p).and_then(|mut r| r.read_to_end(&mut buf)).is_ok()
+                && str::from_utf8(&buf).is_ok()
+        };
+        (self.is_new() || text(&self.golden)) && text(&self.path())
+    }
+
+    // Opens the decoded golden, which is empty if it doesn't exist yet.
+    fn open_golden(&self, e: &Entry) -> Result<Box<dyn Read>> {
+        if self.is_new() {
+            return Ok(Box::new(io::empty()));
+        }
+        Golden::read(e, &self.golden).map_err(|err| Error::io(&self.golden, err))
+    }
+
+    fn open_pending(&self, e: &Entry) -> Result<Box<dyn Read>> {
+        let p = self.path();
+        Golden::read(e, &p).map_err(|err| Error::io(&p, err))
+    }
+
+    /// Writes the diff from the golden to the pending file, using the diff
+    /// style and context from the environment.
+    pub fn diff(&self, w: &mut dyn Write) -> Result<DiffStats> {
+        let e = self.entry();
+        let (golden, actual) = (self.open_golden(&e)?, self.open_pending(&e)?);
+        render(&e, &e.path, DiffStyle::from_env(), context_from_env(), golden, actual, w)
+            .map_err(|err| Error::read(&self.golden, err))
+    }
+
+    /// Counts the changes from the golden to the pending file without
+    /// rendering them.
+    pub fn stats(&self) -> Result<DiffStats> {
+        let e = self.entry();
+        let (golden, actual) = (self.open_golden(&e)?, self.open_pending(&e)?);
+        diff_stats(&e, golden, actual).map_err(|err| Error::read(&self.golden, err))
+    }
+
+    /// The changes from the golden to the pending file, with the context from
+    /// `GOLDEN_CONTEXT`.
+    pub fn hunks(&self) -> Result<Vec<Hunk>> {
+        let e = self.entry();
+        if e.binary {
+            let err = io::Error::new(io::ErrorKind::InvalidInput, "binary file has no hunks");
+            return Err(Error::io(&self.golden, err));
+        }
+        let (golden, actual) = (self.open_golden(&e)?, self.open_pending(&e)?);
+        let mut hunker = Hunker::new(context_from_env());
+        let mut hunks = Vec::new();
+        let _ = LineDiff::new(golden, actual)
+            .run(|op, l| {
+                hunks.extend(hunker.op(op, l));
+                Ok(())
+            })
+            .map_err(|err| Error::read(&self.golden, err))?;
+        hunks.extend(hunker.finish());
+        Ok(hunks)
+    }
+
+    /// Applies |hunks|, which come from |hunks| and may have been edited, to
+    /// the golden file and deletes the pending file. Other changes are
+    /// dropped.
+    pub fn accept_hunks(self, hunks: &[Hunk]) -> Result<()> {
+        let e = self.entry();
+        let old =
+            read_lines(self.open_golden(&e)?).map_err(|err| Error::read(&self.golden, err))?;
+        let new = apply_hunks(&old, hunks).map_err(|err| Error::io(&self.golden, err))?;
+        self.write_golden(&e, new.as_bytes()).map_err(|err| Error::io(&self.golden, err))?;
+        self.reject()
+    }
+
+    // Encodes |content| into a sibling temporary file of the golden and renames
+    // it into place.
+    fn write_golden(&self, e: &Entry, content: &[u8]) -> io::Result<()> {
+        let parent = self.golden.parent().filter(|p| !p.as_os_str().is_empty());
+        let tmp = NamedTempFile::new_in(parent.unwrap_or(Path::new(".")))?;
+        let f = BufWriter::new(tmp.reopen()?);
+        let mut w: Box<dyn Encoder> = match &e.codec {
+            Some(codec) => codec.encoder(Box::new(f))?,
+            None => Box::new(f),
+        };
+        w.write_all(content)?;
+        w.finish()?;
+        // Use the pending file's permissions instead of the temporary file's
+        // private ones.
+        fs::set_permissions(tmp.path(), fs::metadata(self.path())?.permissions())?;
+        tmp.persist(&self.golden)?;
+        Ok(())
+    }
+
+    /// Replaces the golden file with the pending file.
+    pub fn accept(self) -> Result<()> {
+        fs::rename(self.path(), &self.golden).map_err(|e| Error::io(&self.golden, e))
+    }
+
+    /// Deletes the pending file, keeping the golden file.
+    pub fn reject(self) -> Result<()> {
+        let p = self.path();
+        fs::remove_file(&p).map_err(|e| Error::io(&p, e))
+    }
+}
+
+fn find_in(dir: &Path, found: &mut Vec<Pending>) -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let p = entry.map_err(|e| Error::io(dir, e))?.path();
+        if p.is_dir() {
+            let name = p.file_name().unwrap_or_default().to_string_lossy();
+            if !name.starts_with('.') && name != "target" {
+                find_in(&p, found)?;
+            }
+        } else if p.extension().is_some_and(|ext| ext == EXT) {
+            found.push(Pending { golden: p.with_extension("") });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::collections::BTreeSet;
+use std::path::{self, Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+// Golden files claimed by live `Golden`s in this process that opted in.
+static CLAIMED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// The golden files claimed by one `Golden`, released when dropped.
+#[must_use]
+#[derive(Debug, Default)]
+pub(crate) struct Claims {
+    paths: Vec<PathBuf>,
+}
+
+impl Claims {
+    // Claims golden file |p|, returning false if another `Golden` already has.
+    pub(crate) fn claim(&mut self, p: &Path) -> bool {
+        let p = path::absolute(p).unwrap_or_else(|_| p.to_owned());
+        let mut claimed = CLAIMED.lock().unwrap_or_else(PoisonError::into_inner);
+        if !claimed.insert(p.clone()) {
+            return false;
+        }
+        self.paths.push(p);
+        true
+    }
+}
+
+impl Drop for Claims {
+    fn drop(&mut self) {
+        let mut claimed = CLAIMED.lock().unwrap_or_else(PoisonError::into_inner);
+        for p in &self.paths {
+            claimed.remove(p);
+        }
+    }
+}
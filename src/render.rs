@@ -0,0 +1,790 @@
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::{env, fmt, mem};
+
+use colored::control::SHOULD_COLORIZE;
+use colored::Colorize;
+use dissimilar::{diff, Chunk};
+use terminal_size::{terminal_size, Width};
+use unicode_width::UnicodeWidthChar;
+
+use crate::diff::Op;
+
+pub(crate) const DEFAULT_CONTEXT: usize = 3;
+// Used for the side by side view when the terminal width is unknown.
+const DEFAULT_WIDTH: usize = 160;
+
+/// How differences in text golden files are printed.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffStyle {
+    /// Changed lines only, with the changed characters emphasized.
+    Inline,
+    /// Unified diff with hunk headers. File names are relative to the current
+    /// directory, so the diff applies with `patch -p1` from there if the
+    /// golden directory is inside it. Other golden directories are named with
+    /// `..` or by their absolute path, which `patch` refuses to follow.
+    /// Goldens with a codec are diffed decoded, so their diffs have no `a/`
+    /// and `b/` prefixes and don't apply.
+    Unified,
+    /// Golden and actual text in two aligned columns.
+    SideBySide,
+}
+
+impl DiffStyle {
+    // GOLDEN_DIFF selects the style. Otherwise use the inline diff on a
+    // terminal and the unified diff when output is captured, e.g. on CI.
+    pub(crate) fn from_env() -> Self {
+        match env::var("GOLDEN_DIFF").as_deref() {
+            Ok("inline") => Self::Inline,
+            Ok("unified") => Self::Unified,
+            Ok("side-by-side") => Self::SideBySide,
+            _ if io::stdout().is_terminal() => Self::Inline,
+            _ => Self::Unified,
+        }
+    }
+}
+
+pub(crate) fn context_from_env() -> usize {
+    env::var("GOLDEN_CONTEXT").ok().and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_CONTEXT)
+}
+
+// Receives the line diff of one file and writes it out.
+pub(crate) trait Render {
+    fn op(&mut self, op: Op, l: &str) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+// |decoded| is set if |p| is stored encoded, so the diff is of its decoded
+// content.
+pub(crate) fn renderer<'a>(
+    style: DiffStyle,
+    p: &'a Path,
+    decoded: bool,
+    context: usize,
+    w: &'a mut dyn Write,
+) -> Box<dyn Render + 'a> {
+    match style {
+        DiffStyle::Inline => Box::new(Inline::new(p, w)),
+        DiffStyle::Unified => {
+            let mut r = Unified::new(p, context, true, w);
+            r.decoded = decoded;
+            Box::new(r)
+        }
+        DiffStyle::SideBySide => Box::new(SideBySide::new(p, context, w)),
+    }
+}
+
+// Uncolored unified diff, for writing patch files.
+pub(crate) fn patch<'a>(p: &'a Path, context: usize, w: &'a mut dyn Write) -> Box<dyn Render + 'a> {
+    Box::new(Unified::new(p, context, false, w))
+}
+
+// Accumulates the lines of one side of a change, coloring each piece as it is
+// added so highlighting never spans the line prefix.
+struct Marked {
+    op: Op,
+    lines: Vec<(String, bool)>,
+    cur: String,
+}
+
+impl Marked {
+    fn new(op: Op) -> Self {
+        Self { op, lines: Vec::new(), cur: String::new() }
+    }
+
+    fn push(&mut self, s: &str, emph: bool) {
+        for piece in s.split_inclusive('\n') {
+            let text = piece.strip_suffix('\n').unwrap_or(piece);
+            if !text.is_empty() {
+                let text = match (self.op, emph) {
+                    (Op::Delete, false) => text.red(),
+                    (Op::Delete, true) => text.red().reversed(),
+                    (_, false) => text.green(),
+                    (_, true) => text.green().reversed(),
+                };
+                self.cur += &text.to_string();
+            }
+            if piece.ends_with('\n') {
+                self.lines.push((mem::take(&mut self.cur), true));
+            }
+        }
+    }
+
+    fn print(mut self, w: &mut dyn Write) -> io::Result<()> {
+        if !self.cur.is_empty() {
+            self.lines.push((mem::take(&mut self.cur), false));
+        }
+        let sign = if self.op == Op::Delete { "-".red() } else { "+".green() };
+        for (l, newline) in self.lines {
+            writeln!(w, "{sign}{l}")?;
+            if !newline {
+                writeln!(w, "\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Changed blocks larger than this are printed without character emphasis, as
+// diffing characters is quadratic in the size of the block.
+const EMPH_MAX_LINES: usize = 100;
+const EMPH_MAX_BYTES: usize = 1 << 16;
+
+// Prints removed and added lines in full, emphasizing only the characters that
+// changed between each pair of removed and added lines.
+fn print_changes(w: &mut dyn Write, old: &str, new: &str) -> io::Result<()> {
+    if !SHOULD_COLORIZE.should_colorize() {
+        print_plain(w, '-', old)?;
+        return print_plain(w, '+', new);
+    }
+    let mut a = Marked::new(Op::Delete);
+    let mut b = Marked::new(Op::Insert);
+    let (olds, news): (Vec<_>, Vec<_>) =
+        (old.split_inclusive('\n').collect(), new.split_inclusive('\n').collect());
+    let large =
+        olds.len().max(news.len()) > EMPH_MAX_LINES || old.len().max(new.len()) > EMPH_MAX_BYTES;
+    let pairs = if large { 0 } else { olds.len().min(news.len()) };
+    for (o, n) in olds.iter().zip(&news).take(pairs) {
+        for chunk in diff(o, n) {
+            match chunk {
+                Chunk::Equal(s) => {
+                    a.push(s, false);
+                    b.push(s, false);
+                }
+                Chunk::Delete(s) => a.push(s, true),
+                Chunk::Insert(s) => b.push(s, true),
+            }
+        }
+    }
+    olds[pairs..].iter().for_each(|o| a.push(o, false));
+    news[pairs..].iter().for_each(|n| b.push(n, false));
+    a.print(w)?;
+    b.print(w)
+}
+
+// Prints the lines in |s| prefixed with |sign|, without any highlighting.
+fn print_plain(w: &mut dyn Write, sign: char, s: &str) -> io::Result<()> {
+    for l in s.split_inclusive('\n') {
+        write!(w, "{sign}{l}")?;
+        if !l.ends_with('\n') {
+            writeln!(w, "\n\\ No newline at end of file")?;
+        }
+    }
+    Ok(())
+}
+
+// Collects each run of changed lines into a hunk and prints it, prefixed with
+// its location in the golden file, once the diff resynchronizes.
+struct Inline<'a> {
+    p: &'a Path,
+    w: &'a mut dyn Write,
+    line: usize,
+    start: usize,
+    old: String,
+    new: String,
+}
+
+impl<'a> Inline<'a> {
+    fn new(p: &'a Path, w: &'a mut dyn Write) -> Self {
+        Self { p, w, line: 1, start: 1, old: String::new(), new: String::new() }
+    }
+
+    fn print_hunk(&mut self) -> io::Result<()> {
+        if self.old.is_empty() && self.new.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.w, "{}", format!("{}:{}:", self.p.display(), self.start).bold())?;
+        print_changes(self.w, &self.old, &self.new)?;
+        self.old.clear();
+        self.new.clear();
+        Ok(())
+    }
+}
+
+impl Render for Inline<'_> {
+    fn op(&mut self, op: Op, l: &str) -> io::Result<()> {
+        if op != Op::Equal && self.old.is_empty() && self.new.is_empty() {
+            self.start = self.line;
+        }
+        match op {
+            Op::Equal => self.print_hunk()?,
+            Op::Delete => self.old.push_str(l),
+            Op::Insert => self.new.push_str(l),
+        }
+        if op != Op::Insert {
+            self.line += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.print_hunk()
+    }
+}
+
+/// A group of nearby changed lines with some unchanged context around them.
+#[must_use]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    old_start: usize,
+    new_start: usize,
+    lines: Vec<(Op, String)>,
+}
+
+impl Hunk {
+    fn old_ops(&self) -> impl Iterator<Item = &(Op, String)> {
+        self.lines.iter().filter(|(op, _)| *op != Op::Insert)
+    }
+
+    fn old_lines(&self) -> impl Iterator<Item = &str> {
+        self.old_ops().map(|(_, l)| l.as_str())
+    }
+
+    fn new_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().filter(|(op, _)| *op != Op::Delete).map(|(_, l)| l.as_str())
+    }
+
+    /// Writes the hunk in unified diff format, colored if |color| is set.
+    pub fn write(&self, w: &mut dyn Write, color: bool) -> io::Result<()> {
+        let old_len = self.old_lines().count();
+        let new_len = self.new_lines().count();
+        // An empty range refers to the line before it.
+        let old_start = if old_len == 0 { self.old_start - 1 } else { self.old_start };
+        let new_start = if new_len == 0 { self.new_start - 1 } else { self.new_start };
+        let range = format!("@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+        if color {
+            writeln!(w, "{}", range.cyan())?;
+        } else {
+            writeln!(w, "{range}")?;
+        }
+        let changes = |w: &mut dyn Write, old: &str, new: &str| {
+            if color {
+                print_changes(w, old, new)
+            } else {
+                print_plain(w, '-', old)?;
+                print_plain(w, '+', new)
+            }
+        };
+        let mut old = String::new();
+        let mut new = String::new();
+        for (op, l) in &self.lines {
+            match op {
+                Op::Equal => {
+                    changes(w, &old, &new)?;
+                    old.clear();
+                    new.clear();
+                    print_plain(w, ' ', l)?;
+                }
+                Op::Delete => old += l,
+                Op::Insert => new += l,
+            }
+        }
+        changes(w, &old, &new)
+    }
+
+    /// Parses |text|, an edited copy of this hunk in unified diff format.
+    /// Lines starting with "#" are ignored. Only the added lines may change:
+    /// removed and context lines must stay as they are, except that a removed
+    /// line may be turned into a context line to keep it.
+    pub fn edit(&self, text: &str) -> io::Result<Self> {
+        let mut lines: Vec<(Op, String)> = Vec::new();
+        for l in text.split_inclusive('\n') {
+            let op = match l.as_bytes().first() {
+                Some(b' ') => Op::Equal,
+                Some(b'-') => Op::Delete,
+                Some(b'+') => Op::Insert,
+                Some(b'\\') => {
+                    if let Some((_, last)) = lines.last_mut() {
+                        if last.ends_with('\n') {
+                            last.pop();
+                        }
+                    }
+                    continue;
+                }
+                Some(b'#' | b'@') => continue,
+                // Editors may strip the space from empty context lines.
+                Some(b'\n') => {
+                    lines.push((Op::Equal, l.to_owned()));
+                    continue;
+                }
+                _ => {
+                    let msg = format!("unexpected line in edited hunk: {}", l.trim_end());
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+                }
+            };
+            lines.push((op, l[1..].to_owned()));
+        }
+        let hunk = Self { lines, ..*self };
+        // Context lines must also stay context lines, or applying the hunk
+        // would drop them.
+        let same = hunk.old_ops().count() == self.old_ops().count()
+            && hunk.old_ops().zip(self.old_ops()).all(|((op, l), (orig_op, orig))| {
+                l == orig && (*orig_op == Op::Delete || *op == Op::Equal)
+            });
+        if !same {
+            let msg = "edited hunk changes removed or context lines";
+            return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        }
+        Ok(hunk)
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = Vec::new();
+        self.write(&mut out, false).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&out))
+    }
+}
+
+// Applies |hunks| to the lines of |old|. Each hunk must match |old| and come
+// after the previous one.
+pub(crate) fn apply_hunks(old: &[String], hunks: &[Hunk]) -> io::Result<String> {
+    let mut out = String::new();
+    let mut pos = 0;
+    for hunk in hunks {
+        let start = hunk.old_start - 1;
+        let len = hunk.old_lines().count();
+        let ok = start >= pos
+            && old
+                .get(start..start + len)
+                .is_some_and(|o| o.iter().map(String::as_str).eq(hunk.old_lines()));
+        if !ok {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "hunk does not apply"));
+        }
+        out.extend(old[pos..start].iter().map(String::as_str));
+        out.extend(hunk.new_lines());
+        pos = start + len;
+    }
+    out.extend(old[pos..].iter().map(String::as_str));
+    Ok(out)
+}
+
+// Groups a streaming line diff into hunks. Keeps the last |context| equal lines
+// around so they can lead the next hunk, and merges changes separated by at
+// most twice that.
+#[must_use]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Hunker {
+    context: usize,
+    old_line: usize,
+    new_line: usize,
+    before: VecDeque<String>,
+    hunk: Hunk,
+    trailing: usize,
+}
+
+impl Hunker {
+    pub(crate) fn new(context: usize) -> Self {
+        Self { context, old_line: 1, new_line: 1, ..Self::default() }
+    }
+
+    pub(crate) fn op(&mut self, op: Op, l: &str) -> Option<Hunk> {
+        let mut done = None;
+        match op {
+            Op::Equal if self.hunk.lines.is_empty() => {
+                self.before.push_back(l.to_owned());
+                if self.before.len() > self.context {
+                    self.before.pop_front();
+                }
+            }
+            Op::Equal => {
+                self.hunk.lines.push((op, l.to_owned()));
+                self.trailing += 1;
+                if self.trailing > 2 * self.context {
+                    // Too far from the next change, so close the hunk and keep
+                    // the last few lines as leading context for the next one.
+                    let lines = &mut self.hunk.lines;
+                    let tail = lines.split_off(lines.len() - self.trailing + self.context);
+                    done = Some(mem::take(&mut self.hunk));
+                    self.before = tail.into_iter().skip(1).map(|(_, l)| l).collect();
+                }
+            }
+            Op::Delete | Op::Insert => {
+                if self.hunk.lines.is_empty() {
+                    self.hunk.old_start = self.old_line - self.before.len();
+                    self.hunk.new_start = self.new_line - self.before.len();
+                    self.hunk.lines.extend(self.before.drain(..).map(|l| (Op::Equal, l)));
+                }
+                self.hunk.lines.push((op, l.to_owned()));
+                self.trailing = 0;
+            }
+        }
+        if op != Op::Insert {
+            self.old_line += 1;
+        }
+        if op != Op::Delete {
+            self.new_line += 1;
+        }
+        done
+    }
+
+    pub(crate) fn finish(&mut self) -> Option<Hunk> {
+        if self.hunk.lines.is_empty() {
+            return None;
+        }
+        let len = self.hunk.lines.len() - self.trailing.saturating_sub(self.context);
+        self.hunk.lines.truncate(len);
+        Some(mem::take(&mut self.hunk))
+    }
+}
+
+struct Unified<'a> {
+    p: &'a Path,
+    w: &'a mut dyn Write,
+    color: bool,
+    decoded: bool,
+    header: bool,
+    hunker: Hunker,
+}
+
+impl<'a> Unified<'a> {
+    fn new(p: &'a Path, context: usize, color: bool, w: &'a mut dyn Write) -> Self {
+        Self { p, w, color, decoded: false, header: false, hunker: Hunker::new(context) }
+    }
+
+    fn print_hunk(&mut self, hunk: &Hunk) -> io::Result<()> {
+        if !self.header {
+            let p = self.p.display();
+            // The diff of an encoded file doesn't apply to it, so leave out the
+            // prefixes that `patch -p1` expects.
+            let (old, new) = if self.decoded {
+                writeln!(self.w, "# decoded content of {p}")?;
+                (format!("--- {p}"), format!("+++ {p}"))
+            } else {
+                (format!("--- a/{p}"), format!("+++ b/{p}"))
+            };
+            if self.color {
+                writeln!(self.w, "{}\n{}", old.bold(), new.bold())?;
+            } else {
+                writeln!(self.w, "{old}\n{new}")?;
+            }
+            self.header = true;
+        }
+        hunk.write(self.w, self.color)
+    }
+}
+
+impl Render for Unified<'_> {
+    fn op(&mut self, op: Op, l: &str) -> io::Result<()> {
+        match self.hunker.op(op, l) {
+            Some(hunk) => self.print_hunk(&hunk),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.hunker.finish() {
+            Some(hunk) => self.print_hunk(&hunk),
+            None => Ok(()),
+        }
+    }
+}
+
+// Width of the line number gutter in the side by side view.
+const GUTTER: usize = 6;
+
+fn term_width() -> usize {
+    if let Some((Width(w), _)) = terminal_size() {
+        return w as usize;
+    }
+    env::var("COLUMNS").ok().and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_WIDTH)
+}
+
+// Splits |s| into pieces at most |w| columns wide, returning each piece with
+// its display width.
+fn wrap(s: &str, w: usize) -> Vec<(String, usize)> {
+    let s = s.strip_suffix('\n').unwrap_or(s).replace('\t', "    ");
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut cur_w = 0;
+    for c in s.chars() {
+        let cw = c.width().unwrap_or(0);
+        if cur_w + cw > w && !cur.is_empty() {
+            out.push((mem::take(&mut cur), cur_w));
+            cur_w = 0;
+        }
+        cur.push(c);
+        cur_w += cw;
+    }
+    out.push((cur, cur_w));
+    out
+}
+
+// Prints the golden and actual text in two columns, pairing up removed and
+// added lines and wrapping lines that don't fit.
+struct SideBySide<'a> {
+    p: &'a Path,
+    w: &'a mut dyn Write,
+    col: usize,
+    header: bool,
+    hunker: Hunker,
+}
+
+impl<'a> SideBySide<'a> {
+    fn new(p: &'a Path, context: usize, w: &'a mut dyn Write) -> Self {
+        let col = (term_width().saturating_sub(2 * GUTTER + 3) / 2).max(10);
+        Self { p, w, col, header: false, hunker: Hunker::new(context) }
+    }
+
+    fn cell(&self, no: Option<usize>, piece: Option<&(String, usize)>, pad: bool) -> String {
+        let no = no.map_or(String::new(), |no| no.to_string());
+        let (s, w) = piece.map_or(("", 0), |(s, w)| (s.as_str(), *w));
+        let pad = if pad { " ".repeat(self.col - w) } else { String::new() };
+        format!("{no:>width$} {s}{pad}", width = GUTTER - 1)
+    }
+
+    fn print_row(
+        &mut self,
+        old: Option<(usize, &str)>,
+        new: Option<(usize, &str)>,
+        op: Op,
+    ) -> io::Result<()> {
+        let a = old.map_or(Vec::new(), |(_, l)| wrap(l, self.col));
+        let b = new.map_or(Vec::new(), |(_, l)| wrap(l, self.col));
+        let mid = match (op, old.is_some(), new.is_some()) {
+            (Op::Equal, ..) => " ",
+            (_, true, true) => "|",
+            (_, true, false) => "<",
+            _ => ">",
+        };
+        for i in 0..a.len().max(b.len()) {
+            let first = i == 0;
+            let left = self.cell(old.filter(|_| first).map(|(no, _)| no), a.get(i), true);
+            let right = self.cell(new.filter(|_| first).map(|(no, _)| no), b.get(i), false);
+            let left = if op == Op::Equal { left } else { left.red().to_string() };
+            let right = if op == Op::Equal { right } else { right.green().to_string() };
+            writeln!(self.w, "{left} {mid} {right}")?;
+        }
+        Ok(())
+    }
+
+    fn print_pairs(&mut self, old: &[(usize, String)], new: &[(usize, String)]) -> io::Result<()> {
+        for i in 0..old.len().max(new.len()) {
+            let a = old.get(i).map(|(no, l)| (*no, l.as_str()));
+            let b = new.get(i).map(|(no, l)| (*no, l.as_str()));
+            self.print_row(a, b, Op::Delete)?;
+        }
+        Ok(())
+    }
+
+    fn print_hunk(&mut self, hunk: Hunk) -> io::Result<()> {
+        if !self.header {
+            writeln!(self.w, "{}", format!("{}:", self.p.display()).bold())?;
+            self.header = true;
+        }
+        writeln!(self.w, "{}", "-".repeat(2 * (GUTTER + self.col) + 3).cyan())?;
+        let (mut old_no, mut new_no) = (hunk.old_start, hunk.new_start);
+        let mut old = Vec::new();
+        let mut new = Vec::new();
+        for (op, l) in hunk.lines {
+            match op {
+                Op::Equal => {
+                    self.print_pairs(&old, &new)?;
+                    old.clear();
+                    new.clear();
+                    self.print_row(Some((old_no, &l)), Some((new_no, &l)), Op::Equal)?;
+                }
+                Op::Delete => old.push((old_no, l)),
+                Op::Insert => new.push((new_no, l)),
+            }
+            if op != Op::Insert {
+                old_no += 1;
+            }
+            if op != Op::Delete {
+                new_no += 1;
+            }
+        }
+        self.print_pairs(&old, &new)
+    }
+}
+
+impl Render for SideBySide<'_> {
+    fn op(&mut self, op: Op, l: &str) -> io::Result<()> {
+        match self.hunker.op(op, l) {
+            Some(hunk) => self.print_hunk(hunk),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.hunker.finish() {
+            Some(hunk) => self.print_hunk(hunk),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write as _;
+
+    use super::*;
+    use crate::diff::LineDiff;
+
+    fn lines(s: &str) -> Vec<String> {
+        s.split_inclusive('\n').map(str::to_owned).collect()
+    }
+
+    fn numbered(n: usize) -> String {
+        (1..=n).fold(String::new(), |mut s, i| {
+            let _ = writeln!(s, "l{i}");
+            s
+        })
+    }
+
+    fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+        let mut hunker = Hunker::new(DEFAULT_CONTEXT);
+        let mut hunks = Vec::new();
+        let old: Box<dyn io::Read> = Box::new(io::Cursor::new(old.as_bytes().to_vec()));
+        let new: Box<dyn io::Read> = Box::new(io::Cursor::new(new.as_bytes().to_vec()));
+        let _ = LineDiff::new(old, new)
+            .run(|op, l| {
+                hunks.extend(hunker.op(op, l));
+                Ok(())
+            })
+            .unwrap();
+        hunks.extend(hunker.finish());
+        hunks
+    }
+
+    #[test]
+    fn hunk_round_trip() {
+        let old = numbered(10);
+        let new = old.replace("l5\n", "x5\n");
+        let h = hunks(&old, &new);
+        assert_eq!(h.len(), 1);
+        assert_eq!(h[0].to_string(), "@@ -2,7 +2,7 @@\n l2\n l3\n l4\n-l5\n+x5\n l6\n l7\n l8\n");
+        assert_eq!(h[0].edit(&h[0].to_string()).unwrap(), h[0]);
+        assert_eq!(apply_hunks(&lines(&old), &h).unwrap(), new);
+    }
+
+    #[test]
+    fn edit_added_lines() {
+        let old = numbered(10);
+        let h = &hunks(&old, &old.replace("l5\n", "x5\n"))[0];
+        let text = "# comment\n@@ -2,7 +2,7 @@\n l2\n l3\n l4\n-l5\n+y5\n+extra\n l6\n l7\n l8\n";
+        let edited = h.edit(text).unwrap();
+        let want = old.replace("l5\n", "y5\nextra\n");
+        assert_eq!(apply_hunks(&lines(&old), &[edited]).unwrap(), want);
+    }
+
+    #[test]
+    fn edit_keeps_removed_line() {
+        let old = numbered(10);
+        let h = &hunks(&old, &old.replace("l5\n", "x5\n"))[0];
+        let edited = h.edit(&h.to_string().replace("-l5", " l5")).unwrap();
+        let want = old.replace("l5\n", "l5\nx5\n");
+        assert_eq!(apply_hunks(&lines(&old), &[edited]).unwrap(), want);
+    }
+
+    #[test]
+    fn edit_no_newline_at_end() {
+        let old = numbered(3);
+        let h = &hunks(&old, &old.replace("l3\n", "x3\n"))[0];
+        let text = h.to_string().replace("+x3\n", "+x3\n\\ No newline at end of file\n");
+        let edited = h.edit(&text).unwrap();
+        assert_eq!(apply_hunks(&lines(&old), &[edited]).unwrap(), "l1\nl2\nx3");
+    }
+
+    #[test]
+    fn edit_rejects_old_side_changes() {
+        let old = numbered(10);
+        let h = &hunks(&old, &old.replace("l5\n", "x5\n"))[0];
+        let text = h.to_string();
+        for bad in [
+            // Context line turned into a removed line.
+            text.replace(" l4", "-l4"),
+            // Changed context line.
+            text.replace(" l4", " z4"),
+            // Dropped context line.
+            text.replace(" l4\n", ""),
+            // Changed or dropped removed line.
+            text.replace("-l5", "-z5"),
+            text.replace("-l5\n", ""),
+            // Added line in the old file.
+            text.replace("-l5\n", "-l5\n-l6\n"),
+        ] {
+            assert!(h.edit(&bad).is_err(), "{bad}");
+        }
+        assert!(h.edit("?l4\n").is_err());
+    }
+
+    #[test]
+    fn apply_several_hunks() {
+        let old = numbered(30);
+        let new = old.replace("l3\n", "x3\n").replace("l25\n", "");
+        let h = hunks(&old, &new);
+        assert_eq!(h.len(), 2);
+        let old = lines(&old);
+        assert_eq!(apply_hunks(&old, &h).unwrap(), new);
+        // A subset of hunks only applies those changes.
+        assert_eq!(apply_hunks(&old, &h[1..]).unwrap(), numbered(30).replace("l25\n", ""));
+    }
+
+    #[test]
+    fn apply_rejects_mismatch() {
+        let old = numbered(30);
+        let new = old.replace("l3\n", "x3\n").replace("l25\n", "");
+        let h = hunks(&old, &new);
+        // Out of order.
+        assert!(apply_hunks(&lines(&old), &[h[1].clone(), h[0].clone()]).is_err());
+        // Overlapping.
+        assert!(apply_hunks(&lines(&old), &[h[0].clone(), h[0].clone()]).is_err());
+        // Different golden.
+        assert!(apply_hunks(&lines(&old.replace("l4\n", "z4\n")), &h).is_err());
+        // Golden too short.
+        assert!(apply_hunks(&lines(&numbered(20)), &h).is_err());
+    }
+
+    fn render_patch(old: &str, new: &str) -> String {
+        let mut out = Vec::new();
+        {
+            let mut r = patch(Path::new("x.txt"), DEFAULT_CONTEXT, &mut out);
+            let old: Box<dyn io::Read> = Box::new(io::Cursor::new(old.as_bytes().to_vec()));
+            let new: Box<dyn io::Read> = Box::new(io::Cursor::new(new.as_bytes().to_vec()));
+            let _ = LineDiff::new(old, new).run(|op, l| r.op(op, l)).unwrap();
+            r.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn line_over_limit() {
+        // Longer than the chunk size used by the line diff.
+        let long = format!("{}\n", "x".repeat(70_000));
+        let old = format!("{long}{}", numbered(9).replacen("l1\n", "", 1));
+        let new = old.replace("l8\n", "g\n");
+        let out = render_patch(&old, &new);
+        assert_eq!(out, "--- a/x.txt\n+++ b/x.txt\n@@ -5,5 +5,5 @@\n l5\n l6\n l7\n-l8\n+g\n l9\n");
+
+        // The long line as context is printed in full, as one line.
+        let new = old.replace("l3\n", "g\n");
+        let out = render_patch(&old, &new);
+        assert!(
+            out.starts_with(&format!("--- a/x.txt\n+++ b/x.txt\n@@ -1,6 +1,6 @@\n {long} l2\n"))
+        );
+        assert!(!out.contains("No newline"));
+        let h = hunks(&old, &new);
+        let golden = crate::diff::read_lines(Box::new(io::Cursor::new(old.into_bytes()))).unwrap();
+        assert_eq!(apply_hunks(&golden, &h).unwrap(), new);
+    }
+
+    #[test]
+    fn decoded_header() {
+        let mut out = Vec::new();
+        {
+            let mut r = Unified::new(Path::new("g/b.txt.gz"), DEFAULT_CONTEXT, false, &mut out);
+            r.decoded = true;
+            r.op(Op::Delete, "a\n").unwrap();
+            r.op(Op::Insert, "b\n").unwrap();
+            r.finish().unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# decoded content of g/b.txt.gz\n--- g/b.txt.gz\n+++ g/b.txt.gz\n@@ -1,1 +1,1 @@\n-a\n+b\n"
+        );
+    }
+}
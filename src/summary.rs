@@ -0,0 +1,182 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::diff::DiffStats;
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Change {
+    Created,
+    Modified,
+    Unchanged,
+}
+
+impl Change {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Created => "create",
+            Self::Modified => "modify",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+// What an update did, or would do in a dry run, to one golden file. |stats| is
+// None for unchanged goldens and those that could not be diffed.
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Changed<'a> {
+    pub(crate) path: &'a Path,
+    pub(crate) change: Change,
+    pub(crate) stats: Option<DiffStats>,
+}
+
+// Lists the changes, followed by counts of each kind. Unchanged goldens are only
+// listed in a dry run.
+pub(crate) fn write_summary(
+    w: &mut dyn Write,
+    golden: &Path,
+    changes: &[Changed<'_>],
+    dry_run: bool,
+) -> io::Result<()> {
+    let mut total = DiffStats::default();
+    for c in changes {
+        let word = match (c.change, dry_run) {
+            (Change::Created, false) => "created",
+            (Change::Modified, false) => "modified",
+            (Change::Unchanged, false) => continue,
+            (Change::Created, true) => "would create",
+            (Change::Modified, true) => "would modify",
+            (Change::Unchanged, true) => "unchanged",
+        };
+        write!(w, "{word:>8} {}", c.path.display())?;
+        if let Some(st) = c.stats {
+            write!(w, " (+{} -{})", st.added, st.removed)?;
+            total.added += st.added;
+            total.removed += st.removed;
+        }
+        writeln!(w)?;
+    }
+    let count = |kind| changes.iter().filter(|c| c.change == kind).count();
+    let (created, modified, unchanged) =
+        (count(Change::Created), count(Change::Modified), count(Change::Unchanged));
+    let (added, removed) = (total.added, total.removed);
+    if dry_run {
+        writeln!(
+            w,
+            "dry run for golden files in {}: {created} to create, {modified} to modify, \
+             {unchanged} unchanged (+{added} -{removed})",
+            golden.display()
+        )
+    } else {
+        writeln!(
+            w,
+            "updated golden files in {}: {created} created, {modified} modified, \
+             {unchanged} unchanged (+{added} -{removed})",
+            golden.display()
+        )
+    }
+}
+
+// Writes one JSON object per golden file, e.g.
+// {"golden":"tests/golden","path":"a.txt","change":"modify","added":3,"removed":1,"dry_run":true}
+// "added" and "removed" are null if unknown.
+pub(crate) fn write_json(
+    w: &mut dyn Write,
+    golden: &Path,
+    changes: &[Changed<'_>],
+    dry_run: bool,
+) -> io::Result<()> {
+    let golden = json_str(&golden.to_string_lossy());
+    for c in changes {
+        let (added, removed) = match c.stats {
+            Some(st) => (st.added.to_string(), st.removed.to_string()),
+            None => ("null".to_owned(), "null".to_owned()),
+        };
+        writeln!(
+            w,
+            r#"{{"golden":{golden},"path":{},"change":"{}","added":{added},"removed":{removed},"dry_run":{dry_run}}}"#,
+            json_str(&c.path.to_string_lossy()),
+            c.change.name(),
+        )?;
+    }
+    Ok(())
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(json_str("a.txt"), r#""a.txt""#);
+        assert_eq!(json_str(r#"q"b\s"#), r#""q\"b\\s""#);
+        assert_eq!(json_str("n\nr\rt\t"), r#""n\nr\rt\t""#);
+        assert_eq!(json_str("\u{0}\u{1b}\u{7f}\u{85}"), r#""\u0000\u001b\u007f\u0085""#);
+        assert_eq!(json_str("ü 🦀"), "\"ü 🦀\"");
+    }
+
+    #[test]
+    fn json_lines() {
+        let changes = [
+            Changed {
+                path: Path::new("a \"b\".txt"),
+                change: Change::Modified,
+                stats: Some(DiffStats { hunks: 1, added: 3, removed: 1 }),
+            },
+            Changed { path: Path::new("c.bin"), change: Change::Unchanged, stats: None },
+        ];
+        let mut out = Vec::new();
+        write_json(&mut out, Path::new("tests\\golden"), &changes, true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"golden":"tests\\golden","path":"a \"b\".txt","change":"modify","#,
+                r#""added":3,"removed":1,"dry_run":true}"#,
+                "\n",
+                r#"{"golden":"tests\\golden","path":"c.bin","change":"unchanged","#,
+                r#""added":null,"removed":null,"dry_run":true}"#,
+                "\n",
+            )
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let changes = [
+            Changed {
+                path: Path::new("a.txt"),
+                change: Change::Created,
+                stats: Some(DiffStats { hunks: 1, added: 2, removed: 0 }),
+            },
+            Changed { path: Path::new("b.txt"), change: Change::Unchanged, stats: None },
+        ];
+        let mut out = Vec::new();
+        write_summary(&mut out, Path::new("g"), &changes, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " created a.txt (+2 -0)\nupdated golden files in g: 1 created, 0 modified, 1 \
+             unchanged (+2 -0)\n"
+        );
+    }
+}
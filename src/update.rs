@@ -0,0 +1,176 @@
+use std::env;
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+
+use crate::error::{Error, Result};
+
+// Environment variables set by common CI systems.
+const CI_VARS: &[&str] = &[
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "BUILDKITE",
+    "CIRCLECI",
+    "TRAVIS",
+    "JENKINS_URL",
+    "TF_BUILD",
+    "TEAMCITY_VERSION",
+    "APPVEYOR",
+    "BITBUCKET_BUILD_NUMBER",
+    "DRONE",
+];
+
+// The variable showing that we are running on CI, if any.
+pub(crate) fn ci_var() -> Option<&'static str> {
+    CI_VARS.iter().copied().find(|v| {
+        env::var(v).is_ok_and(|v| !v.is_empty() && v != "0" && !v.eq_ignore_ascii_case("false"))
+    })
+}
+
+#[must_use]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Verify,
+    Update,
+    CreateMissing,
+    // Print what would be updated without writing anything.
+    DryRun,
+}
+
+// Which golden files to update and how. UPDATE_GOLDEN is a comma separated
+// list of at most one mode ("1" or "all", "new", "diff") and any number of glob
+// patterns over golden paths, e.g. "new,parser/**". Patterns on their own
+// update all matching goldens. Goldens that don't match are verified. Bare
+// words without a glob character, '/' or '.' are rejected as unknown modes.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Update {
+    pub(crate) mode: Mode,
+    patterns: Vec<Pattern>,
+}
+
+impl Update {
+    pub(crate) fn from_env() -> Result<Self> {
+        Self::parse(&env::var("UPDATE_GOLDEN").unwrap_or_default())
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let mut mode = None;
+        let mut patterns = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|s| !s.is_empty() && *s != "0") {
+            let m = match item {
+                "1" | "all" => Mode::Update,
+                "new" => Mode::CreateMissing,
+                "diff" => Mode::DryRun,
+                // A bare word like "true" is more likely a mistyped mode than
+                // a golden file name.
+                _ if !item.contains(['*', '?', '[', '/', '.']) => {
+                    return Err(Error::InvalidPattern {
+                        pattern: item.to_owned(),
+                        reason: "unknown mode; expected \"1\", \"all\", \"new\", \"diff\" or \
+                                 a glob pattern"
+                            .to_owned(),
+                    });
+                }
+                _ => {
+                    let p = Pattern::new(item).map_err(|e| Error::InvalidPattern {
+                        pattern: item.to_owned(),
+                        reason: e.msg.to_owned(),
+                    })?;
+                    patterns.push(p);
+                    continue;
+                }
+            };
+            if mode.is_some_and(|mode| mode != m) {
+                return Err(Error::InvalidPattern {
+                    pattern: s.to_owned(),
+                    reason: "more than one mode given".to_owned(),
+                });
+            }
+            mode = Some(m);
+        }
+        let mode = match mode {
+            Some(mode) => mode,
+            None if patterns.is_empty() => Mode::Verify,
+            None => Mode::Update,
+        };
+        Ok(Self { mode, patterns })
+    }
+
+    // Whether this writes golden files. Dry runs don't.
+    pub(crate) fn writes(&self) -> bool {
+        matches!(self.mode, Mode::Update | Mode::CreateMissing)
+    }
+
+    // Whether golden path |p| is selected for updating.
+    pub(crate) fn matches(&self, p: &Path) -> bool {
+        let opts = MatchOptions { require_literal_separator: true, ..MatchOptions::new() };
+        self.patterns.is_empty() || self.patterns.iter().any(|pat| pat.matches_path_with(p, opts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(s: &str) -> Mode {
+        Update::parse(s).unwrap().mode
+    }
+
+    #[test]
+    fn modes() {
+        assert_eq!(mode(""), Mode::Verify);
+        assert_eq!(mode("0"), Mode::Verify);
+        assert_eq!(mode(" , "), Mode::Verify);
+        assert_eq!(mode("1"), Mode::Update);
+        assert_eq!(mode("all"), Mode::Update);
+        assert_eq!(mode("new"), Mode::CreateMissing);
+        assert_eq!(mode(" diff "), Mode::DryRun);
+        assert_eq!(mode("1,all"), Mode::Update);
+    }
+
+    #[test]
+    fn modes_and_patterns() {
+        let u = Update::parse("new,parser/**").unwrap();
+        assert_eq!(u.mode, Mode::CreateMissing);
+        assert_eq!(u.patterns, [Pattern::new("parser/**").unwrap()]);
+        // Patterns on their own update.
+        let u = Update::parse("a.txt,b/*").unwrap();
+        assert_eq!(u.mode, Mode::Update);
+        assert_eq!(u.patterns.len(), 2);
+        assert_eq!(mode("0,diff,*.txt"), Mode::DryRun);
+    }
+
+    #[test]
+    fn invalid() {
+        for s in ["1,new", "new,diff,a/*", "true", "yes", "1,parser", "[a"] {
+            assert!(
+                matches!(Update::parse(s), Err(Error::InvalidPattern { .. })),
+                "{s:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let u = Update::parse("*.txt").unwrap();
+        assert!(u.matches(Path::new("a.txt")));
+        // A single star doesn't cross directories.
+        assert!(!u.matches(Path::new("sub/a.txt")));
+
+        let u = Update::parse("**/*.txt").unwrap();
+        assert!(u.matches(Path::new("a.txt")));
+        assert!(u.matches(Path::new("sub/deep/a.txt")));
+        assert!(!u.matches(Path::new("sub/a.bin")));
+
+        let u = Update::parse("parser/**,x/?.bin").unwrap();
+        assert!(u.matches(Path::new("parser/a/b.txt")));
+        assert!(u.matches(Path::new("x/1.bin")));
+        assert!(!u.matches(Path::new("lexer/a.txt")));
+        assert!(!u.matches(Path::new("x/y/1.bin")));
+
+        // No patterns select everything.
+        assert!(Update::parse("1").unwrap().matches(Path::new("any/where.txt")));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::codec::Encoder;
+
+#[must_use]
+#[derive(Debug, Default)]
+struct State {
+    errors: Vec<(PathBuf, io::Error)>,
+    open: Vec<PathBuf>,
+}
+
+// Tracks which writers handed out by a `Golden` are still open and the errors
+// that happened while writing each file.
+#[must_use]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Tracker {
+    state: Arc<Mutex<State>>,
+}
+
+impl Tracker {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn open(&self, p: &Path) {
+        self.state().open.push(p.to_owned());
+    }
+
+    fn close(&self, p: &Path) {
+        let mut st = self.state();
+        if let Some(i) = st.open.iter().position(|q| q == p) {
+            st.open.swap_remove(i);
+        }
+    }
+
+    fn record(&self, p: &Path, e: &io::Error) {
+        self.state().errors.push((p.to_owned(), io::Error::new(e.kind(), e.to_string())));
+    }
+
+    pub(crate) fn is_open(&self, p: &Path) -> bool {
+        self.state().open.iter().any(|q| q == p)
+    }
+
+    pub(crate) fn error(&self, p: &Path) -> Option<io::Error> {
+        let st = self.state();
+        st.errors.iter().find(|(q, _)| q == p).map(|(_, e)| io::Error::new(e.kind(), e.to_string()))
+    }
+}
+
+/// Writes the actual output for one golden file. Dropping it finishes the
+/// file; call |finish| to handle errors directly. Errors are also reported
+/// when the `Golden` is verified.
+#[must_use]
+pub struct GoldenWriter {
+    path: PathBuf,
+    inner: Option<Box<dyn Encoder>>,
+    tracker: Tracker,
+}
+
+impl GoldenWriter {
+    pub(crate) fn new(path: &Path, inner: Box<dyn Encoder>, tracker: Tracker) -> Self {
+        tracker.open(path);
+        Self { path: path.to_owned(), inner: Some(inner), tracker }
+    }
+
+    fn check<T>(&self, r: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &r {
+            self.tracker.record(&self.path, e);
+        }
+        r
+    }
+
+    fn inner(&mut self) -> io::Result<&mut Box<dyn Encoder>> {
+        self.inner.as_mut().ok_or_else(|| io::Error::other("golden writer already finished"))
+    }
+
+    /// Flushes and closes the file, including any compression trailer.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let Some(inner) = self.inner.take() else {
+            return Ok(());
+        };
+        let r = self.check(inner.finish());
+        self.tracker.close(&self.path);
+        r
+    }
+}
+
+impl Write for GoldenWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let r = self.inner().and_then(|w| w.write(buf));
+        self.check(r)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let r = self.inner().and_then(Write::flush);
+        self.check(r)
+    }
+}
+
+impl Drop for GoldenWriter {
+    fn drop(&mut self) {
+        // Any error is recorded in the tracker and reported by the Golden.
+        let _ = self.close();
+    }
+}
+
+impl fmt::Debug for GoldenWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GoldenWriter").field("path", &self.path).finish_non_exhaustive()
+    }
+}